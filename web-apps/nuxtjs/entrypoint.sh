@@ -11,6 +11,10 @@
 # UNINSTALL_NODE_PACKAGES - Additional packages to uninstall via apt-get.
 
 # SUPERVISOR_API_KEY - The API key for authenticating requests to the supervisor API.
+# SUPERVISOR_WEBHOOK_SECRET - The shared secret for verifying signed webhook deliveries.
+# SUPERVISOR_CONFIG - Path to the supervisor config file (default: /home/container/supervisor.toml, if present).
+#   All supervisor settings can be set there or overridden with SUPERVISOR_* variables,
+#   see supervisor/supervisor.example.toml for the full list.
 
 function install_or_update_bun {
     #if [ ! -f /home/container/.bun/bin/bun ]; then
@@ -38,6 +42,7 @@ function create_directories {
     mkdir -p /home/container/.app/tmp
     mkdir -p /home/container/.app/instance/1/
     mkdir -p /home/container/.app/instance/2/
+    mkdir -p /home/container/.app/releases/
 }
 
 
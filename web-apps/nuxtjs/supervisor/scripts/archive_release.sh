@@ -0,0 +1,29 @@
+#!/bin/bash
+
+set -e
+
+APP_DIR="${SUPERVISOR_APP_DIR:-/home/container/.app}"
+
+INSTANCE_NUMBER=$1
+COMMIT_SHA=$2
+
+if [ ${INSTANCE_NUMBER} != "1" ] && [ ${INSTANCE_NUMBER} != "2" ]; then
+    echo "Invalid instance number provided. Must be 1 or 2."
+    exit 1
+fi
+
+if [ -z "${COMMIT_SHA}" ]; then
+    echo "No commit SHA provided to archive the release under."
+    exit 1
+fi
+
+RELEASE_DIR=${APP_DIR}/releases/${COMMIT_SHA}
+
+rm -rf ${RELEASE_DIR}
+mkdir -p ${RELEASE_DIR}
+
+cp -r ${APP_DIR}/instance/${INSTANCE_NUMBER}/* ${RELEASE_DIR}/
+# archive time, rollbacks go back through releases in this order
+date +%s > ${RELEASE_DIR}/.created_at
+
+echo "Instance ${INSTANCE_NUMBER} archived as release ${COMMIT_SHA}."
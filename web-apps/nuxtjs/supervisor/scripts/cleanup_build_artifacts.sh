@@ -1,9 +1,13 @@
 #!/bin/bash
 
-if [ -d "/home/container/.app/git-repo/.git" ]; then
+set -e
+
+APP_DIR="${SUPERVISOR_APP_DIR:-/home/container/.app}"
+
+if [ -d "${APP_DIR}/git-repo/.git" ]; then
 
     echo "Cleaning up build artifacts from Git repository..."
-    cd /home/container/.app/git-repo
+    cd ${APP_DIR}/git-repo
 
     rm -rf node_modules
     rm -rf .nuxt
@@ -1,5 +1,7 @@
 #!/bin/bash
 
+APP_DIR="${SUPERVISOR_APP_DIR:-/home/container/.app}"
+
 INSTANCE_NUMBER=$1
 
 if [ ${INSTANCE_NUMBER} != "1" ] && [ ${INSTANCE_NUMBER} != "2" ]; then
@@ -7,4 +9,4 @@ if [ ${INSTANCE_NUMBER} != "1" ] && [ ${INSTANCE_NUMBER} != "2" ]; then
     exit 1
 fi
 
-rm -rf /home/container/.app/instance/${INSTANCE_NUMBER}/*
+rm -rf ${APP_DIR}/instance/${INSTANCE_NUMBER}/*
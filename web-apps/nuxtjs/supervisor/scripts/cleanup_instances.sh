@@ -1,4 +1,6 @@
 #!/bin/bash
 
-rm -rf /home/container/.app/instance/1/*
-rm -rf /home/container/.app/instance/2/*
+APP_DIR="${SUPERVISOR_APP_DIR:-/home/container/.app}"
+
+rm -rf ${APP_DIR}/instance/1/*
+rm -rf ${APP_DIR}/instance/2/*
@@ -1,14 +1,16 @@
 #!/bin/bash
 
-if [ -d "/home/container/.app/git-repo/.git" ]; then
+set -e
 
-    echo "Creating new build from Git repository..."
-    cd /home/container/.app/git-repo
+APP_DIR="${SUPERVISOR_APP_DIR:-/home/container/.app}"
+RUNTIME="${SUPERVISOR_RUNTIME:-bun}"
+
+if [ -d "${APP_DIR}/git-repo/.git" ]; then
 
-    /usr/local/share/supervisor/scripts/cleanup_build_artifacts.sh
+    echo "Creating new build from Git repository..."
+    cd ${APP_DIR}/git-repo
 
-    bun install
-    bun run build
+    ${RUNTIME} run build
 
     cd /home/container
 
@@ -0,0 +1,23 @@
+#!/bin/bash
+
+set -e
+
+APP_DIR="${SUPERVISOR_APP_DIR:-/home/container/.app}"
+RUNTIME="${SUPERVISOR_RUNTIME:-bun}"
+SCRIPTS_DIR="${SUPERVISOR_SCRIPTS_DIR:-/usr/local/share/supervisor/scripts}"
+
+if [ -d "${APP_DIR}/git-repo/.git" ]; then
+
+    echo "Installing dependencies for Git repository..."
+    cd ${APP_DIR}/git-repo
+
+    ${SCRIPTS_DIR}/cleanup_build_artifacts.sh
+
+    ${RUNTIME} install
+
+    cd /home/container
+
+else
+    echo "No Git repository found to install dependencies for."
+    exit 1
+fi
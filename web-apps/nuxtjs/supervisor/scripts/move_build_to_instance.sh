@@ -1,5 +1,9 @@
 #!/bin/bash
 
+set -e
+
+APP_DIR="${SUPERVISOR_APP_DIR:-/home/container/.app}"
+
 INSTANCE_NUMBER=$1
 
 if [ ${INSTANCE_NUMBER} != "1" ] && [ ${INSTANCE_NUMBER} != "2" ]; then
@@ -7,17 +11,17 @@ if [ ${INSTANCE_NUMBER} != "1" ] && [ ${INSTANCE_NUMBER} != "2" ]; then
     exit 1
 fi
 
-if [ ! -d "/home/container/.app/git-repo/.output" ]; then
+if [ ! -d "${APP_DIR}/git-repo/.output" ]; then
     echo "No build found to move. Please create a build first."
     exit 1
 fi
 
-rm -rf /home/container/.app/instance/${INSTANCE_NUMBER}/*
+rm -rf ${APP_DIR}/instance/${INSTANCE_NUMBER}/*
 
-cp -r /home/container/.app/git-repo/.output/* /home/container/.app/instance/${INSTANCE_NUMBER}/
+cp -r ${APP_DIR}/git-repo/.output/* ${APP_DIR}/instance/${INSTANCE_NUMBER}/
 
-rm -rf /home/container/.app/git-repo/.output
-rm -rf /home/container/.app/git-repo/.nuxt
-rm -rf /home/container/.app/git-repo/node_modules
+rm -rf ${APP_DIR}/git-repo/.output
+rm -rf ${APP_DIR}/git-repo/.nuxt
+rm -rf ${APP_DIR}/git-repo/node_modules
 
 echo "Build moved to instance ${INSTANCE_NUMBER} successfully."
\ No newline at end of file
@@ -0,0 +1,26 @@
+#!/bin/bash
+
+set -e
+
+APP_DIR="${SUPERVISOR_APP_DIR:-/home/container/.app}"
+
+COMMIT_SHA=$1
+INSTANCE_NUMBER=$2
+
+if [ ${INSTANCE_NUMBER} != "1" ] && [ ${INSTANCE_NUMBER} != "2" ]; then
+    echo "Invalid instance number provided. Must be 1 or 2."
+    exit 1
+fi
+
+RELEASE_DIR=${APP_DIR}/releases/${COMMIT_SHA}
+
+if [ -z "${COMMIT_SHA}" ] || [ ! -d "${RELEASE_DIR}" ]; then
+    echo "No release found for commit ${COMMIT_SHA}."
+    exit 1
+fi
+
+rm -rf ${APP_DIR}/instance/${INSTANCE_NUMBER}/*
+
+cp -r ${RELEASE_DIR}/* ${APP_DIR}/instance/${INSTANCE_NUMBER}/
+
+echo "Release ${COMMIT_SHA} moved to instance ${INSTANCE_NUMBER} successfully."
@@ -0,0 +1,25 @@
+#!/bin/bash
+
+set -e
+
+APP_DIR="${SUPERVISOR_APP_DIR:-/home/container/.app}"
+
+KEEP_RELEASES=$1
+
+if ! [[ "${KEEP_RELEASES}" =~ ^[0-9]+$ ]]; then
+    echo "Invalid number of releases to keep provided."
+    exit 1
+fi
+
+if [ ! -d "${APP_DIR}/releases" ]; then
+    exit 0
+fi
+
+cd ${APP_DIR}/releases
+
+ls -1t | tail -n +$((KEEP_RELEASES + 1)) | while read -r RELEASE; do
+    rm -rf "./${RELEASE}"
+    echo "Removed old release ${RELEASE}."
+done
+
+cd /home/container
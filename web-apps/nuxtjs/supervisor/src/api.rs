@@ -1,17 +1,40 @@
+use crate::config;
+use crate::git;
+use crate::instance_handler::{self, Trigger};
+use crate::preview;
+use crate::proxy;
+use crate::webhook::{self, WebhookEvent};
 use axum::{
     Json, Router,
-    extract::Query,
-    http::StatusCode,
+    body::Bytes,
+    extract::{Path, Query},
+    http::{HeaderMap, StatusCode, header},
     response::{IntoResponse, Response},
-    routing::post,
+    routing::{get, post},
 };
 use serde::{Deserialize, Serialize};
-use crate::instance_handler;
 
 pub async fn start_api() {
-    let app = Router::new().route("/_supervisor/webhook/update", post(webhook_update));
+    let app = Router::new()
+        .route("/_supervisor/webhook/update", post(webhook_update))
+        .route("/_supervisor/webhook/github", post(webhook_github))
+        .route("/_supervisor/webhook/gitea", post(webhook_gitea))
+        .route("/_supervisor/webhook/gitlab", post(webhook_gitlab))
+        .route("/_supervisor/jobs/{id}", get(job_status))
+        .route("/_supervisor/jobs/{id}/logs", get(job_logs))
+        .route("/_supervisor/status", get(supervisor_status))
+        .route("/_supervisor/releases", get(list_releases))
+        .route("/_supervisor/deployments", get(list_deployments))
+        .route("/_supervisor/rollback", post(rollback))
+        .route("/_supervisor/cancel", post(cancel_deploy))
+        .route("/_supervisor/promote", post(promote))
+        .route("/_supervisor/discard", post(discard))
+        .route("/_supervisor/canary/promote", post(promote))
+        .route("/_supervisor/canary/abort", post(discard))
+        .route("/_supervisor/preview", get(start_preview))
+        .route("/_supervisor/preview/exit", get(exit_preview));
 
-    let listener = tokio::net::TcpListener::bind("127.0.0.1:19180")
+    let listener = tokio::net::TcpListener::bind(config::get().api.listen.as_str())
         .await
         .unwrap();
 
@@ -19,43 +42,495 @@ pub async fn start_api() {
     axum::serve(listener, app).await.unwrap();
 }
 
+const API_KEY_HEADER: &str = "x-api-key";
+
 #[derive(Deserialize)]
 struct AuthQuery {
     apikey: Option<String>,
 }
 
-async fn webhook_update(Query(query): Query<AuthQuery>) -> Response {
-    let expected_key = std::env::var("SUPERVISOR_API_KEY").ok();
-
+fn is_authorized(query: &AuthQuery) -> bool {
     // Auth check via `apikey` query parameter
-    let authorized: bool = match (expected_key.as_deref(), query.apikey.as_deref()) {
-        (Some(expected), Some(provided)) if !expected.is_empty() && provided == expected => true,
-        _ => false,
+    api_key_matches(query.apikey.as_deref())
+}
+
+/// Auth check via `Authorization: Bearer <key>` or `X-Api-Key: <key>`, for endpoints
+/// whose URL must not carry the key, e.g. because it ends up in the browser history.
+fn is_authorized_by_header(headers: &HeaderMap) -> bool {
+    let header_value = |name: &str| headers.get(name).and_then(|value| value.to_str().ok());
+    let provided = header_value(header::AUTHORIZATION.as_str())
+        .and_then(|value| value.strip_prefix("Bearer "))
+        .or_else(|| header_value(API_KEY_HEADER));
+    api_key_matches(provided.map(str::trim))
+}
+
+fn api_key_matches(provided: Option<&str>) -> bool {
+    let expected_key = config::get().api.api_key.as_deref();
+    matches!(
+        (expected_key, provided),
+        (Some(expected), Some(provided)) if !expected.is_empty() && provided == expected
+    )
+}
+
+fn unauthorized() -> Response {
+    let body = ErrorResponse {
+        success: false,
+        message: "Unauthorized: invalid or missing API key".to_string(),
     };
+    (StatusCode::UNAUTHORIZED, Json(body)).into_response()
+}
+
+#[derive(Deserialize)]
+struct UpdateQuery {
+    apikey: Option<String>,
+    /// Branch, tag or commit to deploy instead of the tracked branch.
+    #[serde(rename = "ref")]
+    git_ref: Option<String>,
+    /// Rebuild even if the commit is already deployed.
+    #[serde(default)]
+    force: bool,
+}
+
+async fn webhook_update(
+    Query(query): Query<UpdateQuery>,
+    headers: HeaderMap,
+    body: Bytes,
+) -> Response {
+    let provider = webhook::detect(&headers);
+    let auth = AuthQuery {
+        apikey: query.apikey,
+    };
+    let git_ref = query.git_ref.filter(|git_ref| !git_ref.is_empty());
+    handle_webhook(provider, auth, git_ref, query.force, headers, body).await
+}
 
-    if !authorized {
-        let body = ErrorResponse {
-            success: false,
-            message: "Unauthorized: invalid or missing API key".to_string(),
+async fn webhook_github(
+    Query(query): Query<AuthQuery>,
+    headers: HeaderMap,
+    body: Bytes,
+) -> Response {
+    handle_webhook(
+        Some(webhook::Provider::Github),
+        query,
+        None,
+        false,
+        headers,
+        body,
+    )
+    .await
+}
+
+async fn webhook_gitea(
+    Query(query): Query<AuthQuery>,
+    headers: HeaderMap,
+    body: Bytes,
+) -> Response {
+    handle_webhook(
+        Some(webhook::Provider::Gitea),
+        query,
+        None,
+        false,
+        headers,
+        body,
+    )
+    .await
+}
+
+async fn webhook_gitlab(
+    Query(query): Query<AuthQuery>,
+    headers: HeaderMap,
+    body: Bytes,
+) -> Response {
+    handle_webhook(
+        Some(webhook::Provider::Gitlab),
+        query,
+        None,
+        false,
+        headers,
+        body,
+    )
+    .await
+}
+
+/// Requests without a provider are plain update triggers and need the API key.
+/// Pushes deploy the pushed commit, plain triggers `requested_ref` or the tracked branch.
+async fn handle_webhook(
+    provider: Option<webhook::Provider>,
+    query: AuthQuery,
+    requested_ref: Option<String>,
+    force: bool,
+    headers: HeaderMap,
+    body: Bytes,
+) -> Response {
+    let api_key_valid = is_authorized(&query);
+
+    let (git_ref, expected_commit) = if let Some(provider) = provider {
+        let webhook_config = &config::get().webhook;
+        let event = match webhook::parse(
+            provider,
+            &headers,
+            &body,
+            webhook_config.secret.as_deref(),
+            api_key_valid,
+        ) {
+            Ok(event) => event,
+            Err(err) => return webhook_error(err),
         };
-        return (StatusCode::UNAUTHORIZED, Json(body)).into_response();
+
+        match event {
+            WebhookEvent::Ping => return acknowledge("Pong. Webhook is set up correctly."),
+            WebhookEvent::Other(event) => {
+                return acknowledge(&format!("Ignored '{event}' event."));
+            }
+            WebhookEvent::Push {
+                git_ref,
+                deleted: true,
+                ..
+            } => {
+                return acknowledge(&format!("Ignored push to {git_ref}."));
+            }
+            WebhookEvent::Push {
+                git_ref, commit, ..
+            } => {
+                // without a configured branch, pushes to the checked out branch deploy
+                let branch = match &webhook_config.branch {
+                    Some(branch) => Some(branch.clone()),
+                    None => git::tracked_branch().await.ok(),
+                };
+                if !webhook::branch_matches(&git_ref, branch.as_deref()) {
+                    return acknowledge(&format!("Ignored push to {git_ref}."));
+                }
+                tracing::info!(
+                    target: "supervisor",
+                    provider = provider.as_str(),
+                    git_ref = %git_ref,
+                    commit = commit.as_deref().unwrap_or_default(),
+                    "push webhook received"
+                );
+                // the push deploys the tracked branch, its commit is only what to expect there
+                (None, commit)
+            }
+        }
+    } else if api_key_valid {
+        (requested_ref, None)
+    } else {
+        return unauthorized();
+    };
+
+    // shedule update, the deploy itself runs in the background
+    let message = match git_ref.as_deref().or(expected_commit.as_deref()) {
+        Some(git_ref) => {
+            format!("Update to {git_ref} was added to the queue and will be processed shortly.")
+        }
+        None => "Update was added to the queue and will be processed shortly.".to_string(),
+    };
+    let trigger = if provider.is_some() {
+        Trigger::Webhook
+    } else {
+        Trigger::Api
+    };
+    let job_id =
+        instance_handler::InstanceHandler::request_update(git_ref, expected_commit, force, trigger);
+
+    let response = WebhookUpdateResponse {
+        success: true,
+        message,
+        job_id,
+    };
+
+    (StatusCode::ACCEPTED, Json(response)).into_response()
+}
+
+fn webhook_error(err: webhook::WebhookError) -> Response {
+    let status = match err {
+        webhook::WebhookError::InvalidPayload(_) => StatusCode::BAD_REQUEST,
+        _ => StatusCode::UNAUTHORIZED,
+    };
+    let body = ErrorResponse {
+        success: false,
+        message: err.to_string(),
+    };
+    (status, Json(body)).into_response()
+}
+
+/// Accepts a webhook delivery without deploying anything.
+fn acknowledge(message: &str) -> Response {
+    let body = MessageResponse {
+        success: true,
+        message: message.to_string(),
+    };
+    (StatusCode::OK, Json(body)).into_response()
+}
+
+#[derive(Deserialize)]
+struct RollbackQuery {
+    apikey: Option<String>,
+    commit: Option<String>,
+}
+
+async fn rollback(Query(query): Query<RollbackQuery>) -> Response {
+    let auth = AuthQuery {
+        apikey: query.apikey,
+    };
+    if !is_authorized(&auth) {
+        return unauthorized();
     }
 
-    // shedule update
-    instance_handler::InstanceHandler::on_update().await;
+    let job_id = instance_handler::InstanceHandler::enqueue_rollback(
+        query.commit.filter(|commit| !commit.is_empty()),
+        Trigger::Api,
+    );
 
     let response = WebhookUpdateResponse {
         success: true,
-        message: "Update was added to the queue and will be processed shortly.".to_string(),
+        message: "Rollback was added to the queue and will be processed shortly.".to_string(),
+        job_id,
+    };
+
+    (StatusCode::ACCEPTED, Json(response)).into_response()
+}
+
+async fn cancel_deploy(Query(query): Query<AuthQuery>) -> Response {
+    if !is_authorized(&query) {
+        return unauthorized();
+    }
+
+    match instance_handler::InstanceHandler::cancel_deploy().await {
+        Ok(job_id) => {
+            let response = WebhookUpdateResponse {
+                success: true,
+                message: format!("Deployment job #{job_id} was cancelled."),
+                job_id,
+            };
+            (StatusCode::OK, Json(response)).into_response()
+        }
+        Err(err) => {
+            let body = ErrorResponse {
+                success: false,
+                message: err.to_string(),
+            };
+            (StatusCode::CONFLICT, Json(body)).into_response()
+        }
+    }
+}
+
+async fn promote(Query(query): Query<AuthQuery>) -> Response {
+    if !is_authorized(&query) {
+        return unauthorized();
+    }
+
+    promotion_response(
+        instance_handler::InstanceHandler::promote(),
+        "is being promoted",
+    )
+}
+
+async fn discard(Query(query): Query<AuthQuery>) -> Response {
+    if !is_authorized(&query) {
+        return unauthorized();
+    }
+
+    promotion_response(
+        instance_handler::InstanceHandler::discard(),
+        "is being discarded",
+    )
+}
+
+fn promotion_response(
+    result: Result<u64, instance_handler::PromotionError>,
+    action: &str,
+) -> Response {
+    match result {
+        Ok(job_id) => {
+            let response = WebhookUpdateResponse {
+                success: true,
+                message: format!("New build of deployment job #{job_id} {action}."),
+                job_id,
+            };
+            (StatusCode::ACCEPTED, Json(response)).into_response()
+        }
+        Err(err) => {
+            let body = ErrorResponse {
+                success: false,
+                message: err.to_string(),
+            };
+            (StatusCode::CONFLICT, Json(body)).into_response()
+        }
+    }
+}
+
+/// Sets the preview cookie, so the browser that makes this request reaches the standby slot.
+/// Takes the API key from a header only, the token in the response already grants access.
+async fn start_preview(headers: HeaderMap) -> Response {
+    if !is_authorized_by_header(&headers) {
+        return unauthorized();
+    }
+    let Some(preview) = preview::issue() else {
+        return unauthorized();
+    };
+
+    let message = match proxy::current_preview_backend() {
+        Some(_) => "Preview enabled. Your requests go to the new build in the standby slot.",
+        None => {
+            "Preview enabled. No new build is waiting in the standby slot, so requests go to the main instance until one is."
+        }
+    };
+    let cookie = format!(
+        "{}={}; Path=/; Max-Age={}; HttpOnly; SameSite=Lax",
+        preview::COOKIE_NAME,
+        preview.token,
+        config::get().preview.ttl_secs
+    );
+    let response = PreviewResponse {
+        success: true,
+        message: message.to_string(),
+        token: preview.token,
+        expires_at: preview.expires_at,
+    };
+
+    (
+        StatusCode::OK,
+        [(header::SET_COOKIE, cookie)],
+        Json(response),
+    )
+        .into_response()
+}
+
+async fn exit_preview(headers: HeaderMap) -> Response {
+    if !is_authorized_by_header(&headers) {
+        return unauthorized();
+    }
+
+    let cookie = format!(
+        "{}=; Path=/; Max-Age=0; HttpOnly; SameSite=Lax",
+        preview::COOKIE_NAME
+    );
+    let body = MessageResponse {
+        success: true,
+        message: "Preview disabled.".to_string(),
+    };
+    (StatusCode::OK, [(header::SET_COOKIE, cookie)], Json(body)).into_response()
+}
+
+async fn list_releases(Query(query): Query<AuthQuery>) -> Response {
+    if !is_authorized(&query) {
+        return unauthorized();
+    }
+
+    (
+        StatusCode::OK,
+        Json(instance_handler::InstanceHandler::releases()),
+    )
+        .into_response()
+}
+
+#[derive(Deserialize)]
+struct HistoryQuery {
+    apikey: Option<String>,
+    limit: Option<usize>,
+}
+
+async fn list_deployments(Query(query): Query<HistoryQuery>) -> Response {
+    let auth = AuthQuery {
+        apikey: query.apikey,
+    };
+    if !is_authorized(&auth) {
+        return unauthorized();
+    }
+
+    let limit = query.limit.unwrap_or(50);
+    (
+        StatusCode::OK,
+        Json(instance_handler::InstanceHandler::history(limit)),
+    )
+        .into_response()
+}
+
+async fn job_status(Path(id): Path<u64>, Query(query): Query<AuthQuery>) -> Response {
+    if !is_authorized(&query) {
+        return unauthorized();
+    }
+
+    match instance_handler::InstanceHandler::job(id) {
+        Some(job) => (StatusCode::OK, Json(job)).into_response(),
+        None => {
+            let body = ErrorResponse {
+                success: false,
+                message: format!("No deployment job with id {id}"),
+            };
+            (StatusCode::NOT_FOUND, Json(body)).into_response()
+        }
+    }
+}
+
+async fn job_logs(Path(id): Path<u64>, Query(query): Query<AuthQuery>) -> Response {
+    if !is_authorized(&query) {
+        return unauthorized();
+    }
+
+    match instance_handler::InstanceHandler::deploy_log(id) {
+        Some(log) => (
+            StatusCode::OK,
+            [(header::CONTENT_TYPE, "text/plain; charset=utf-8")],
+            log,
+        )
+            .into_response(),
+        None => {
+            let body = ErrorResponse {
+                success: false,
+                message: format!("No deploy log for job {id}"),
+            };
+            (StatusCode::NOT_FOUND, Json(body)).into_response()
+        }
+    }
+}
+
+async fn supervisor_status(Query(query): Query<AuthQuery>) -> Response {
+    if !is_authorized(&query) {
+        return unauthorized();
+    }
+
+    let response = StatusResponse {
+        success: true,
+        active_backend: proxy::current_world_backend(),
+        preview_backend: proxy::current_preview_backend(),
+        backend_ready: proxy::backend_ready(),
+        status: instance_handler::InstanceHandler::status_snapshot(),
     };
 
     (StatusCode::OK, Json(response)).into_response()
 }
 
+#[derive(Serialize)]
+struct StatusResponse {
+    success: bool,
+    active_backend: Option<String>,
+    preview_backend: Option<String>,
+    backend_ready: bool,
+    #[serde(flatten)]
+    status: instance_handler::InstanceStatus,
+}
+
 #[derive(Serialize)]
 struct WebhookUpdateResponse {
     success: bool,
     message: String,
+    job_id: u64,
+}
+
+#[derive(Serialize)]
+struct PreviewResponse {
+    success: bool,
+    message: String,
+    /// Also accepted in an `X-Supervisor-Slot` header.
+    token: String,
+    expires_at: u64,
+}
+
+#[derive(Serialize)]
+struct MessageResponse {
+    success: bool,
+    message: String,
 }
 
 #[derive(Serialize)]
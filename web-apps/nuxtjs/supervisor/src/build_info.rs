@@ -0,0 +1,101 @@
+use crate::config;
+use crate::utils;
+use serde::{Deserialize, Serialize};
+use std::path::PathBuf;
+use tokio::process::Command;
+
+// checked in order, the first one present is hashed
+const LOCKFILES: [&str; 5] = [
+    "bun.lock",
+    "bun.lockb",
+    "package-lock.json",
+    "yarn.lock",
+    "pnpm-lock.yaml",
+];
+
+/// What was built into an instance slot, stored next to the slot directory.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct BuildInfo {
+    pub commit: Option<String>,
+    pub lockfile_hash: Option<String>,
+    pub built_at: u64,
+}
+
+impl BuildInfo {
+    pub fn new(commit: Option<String>, lockfile_hash: Option<String>) -> Self {
+        Self {
+            commit,
+            lockfile_hash,
+            built_at: utils::unix_timestamp(),
+        }
+    }
+
+    pub fn read(instance_number: &str) -> Option<Self> {
+        let contents = std::fs::read_to_string(path(instance_number)).ok()?;
+        serde_json::from_str(&contents).ok()
+    }
+
+    pub fn write(&self, instance_number: &str) {
+        let result = serde_json::to_string_pretty(self)
+            .map_err(std::io::Error::other)
+            .and_then(|json| std::fs::write(path(instance_number), json));
+        if let Err(e) = result {
+            eprintln!(
+                "Error writing build info for instance {}: {}",
+                instance_number, e
+            );
+        }
+    }
+
+    pub fn remove(instance_number: &str) {
+        let _ = std::fs::remove_file(path(instance_number));
+    }
+}
+
+fn path(instance_number: &str) -> PathBuf {
+    config::get()
+        .paths
+        .app_dir
+        .join("instance")
+        .join(format!("{instance_number}.build.json"))
+}
+
+/// Hash of the repository's dependency lockfile, as computed by `git hash-object`.
+pub async fn lockfile_hash() -> Option<String> {
+    let repo_dir = config::get().git_repo_dir();
+    let lockfile = LOCKFILES
+        .iter()
+        .map(|name| repo_dir.join(name))
+        .find(|path| path.is_file())?;
+
+    let output = Command::new("git")
+        .arg("hash-object")
+        .arg(&lockfile)
+        .output()
+        .await
+        .ok()?;
+    if !output.status.success() {
+        return None;
+    }
+
+    let hash = String::from_utf8_lossy(&output.stdout).trim().to_string();
+    if hash.is_empty() { None } else { Some(hash) }
+}
+
+/// Finds a slot that already holds a build of `commit` with the same dependencies.
+pub fn find_reusable(commit: Option<&str>, lockfile_hash: Option<&str>) -> Option<&'static str> {
+    let commit = commit?;
+    let config = config::get();
+
+    ["1", "2"].into_iter().find(|instance_number| {
+        let Some(info) = BuildInfo::read(instance_number) else {
+            return false;
+        };
+        info.commit.as_deref() == Some(commit)
+            && info.lockfile_hash.as_deref() == lockfile_hash
+            && config
+                .instance_dir(instance_number)
+                .join(&config.instances.entry_point)
+                .is_file()
+    })
+}
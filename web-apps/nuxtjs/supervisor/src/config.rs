@@ -0,0 +1,1084 @@
+use once_cell::sync::OnceCell;
+use serde::Deserialize;
+use std::fmt;
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::time::Duration;
+
+use crate::health::{HealthCheckConfig, HealthCheckMode};
+
+const DEFAULT_CONFIG_PATH: &str = "/home/container/supervisor.toml";
+
+static CONFIG: OnceCell<Config> = OnceCell::new();
+
+/// Supervisor settings, read from a TOML file and overridden by `SUPERVISOR_*` environment variables.
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub proxy: ProxyConfig,
+    pub api: ApiConfig,
+    pub webhook: WebhookConfig,
+    pub git: GitConfig,
+    pub deploy: DeployConfig,
+    pub canary: CanaryConfig,
+    pub auto_rollback: AutoRollbackConfig,
+    pub preview: PreviewConfig,
+    pub shadow: ShadowConfig,
+    pub timeouts: TimeoutsConfig,
+    pub instances: InstancesConfig,
+    pub paths: PathsConfig,
+    pub releases: ReleasesConfig,
+    pub history: HistoryConfig,
+    pub logs: LogsConfig,
+    pub shutdown: ShutdownConfig,
+    pub restart: RestartConfig,
+    pub health: HealthConfig,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ProxyConfig {
+    /// Public address the reverse proxy listens on.
+    pub listen: String,
+    /// HTML file served with a 503 while no healthy backend exists yet.
+    pub unavailable_page: Option<PathBuf>,
+    /// Value of the `Retry-After` header sent with the unavailable page.
+    pub retry_after_secs: u64,
+}
+
+impl Default for ProxyConfig {
+    fn default() -> Self {
+        Self {
+            listen: "0.0.0.0:19130".to_string(),
+            unavailable_page: None,
+            retry_after_secs: 30,
+        }
+    }
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ApiConfig {
+    /// Local address of the management API, the proxy forwards `/_supervisor` here.
+    pub listen: String,
+    pub api_key: Option<String>,
+}
+
+impl Default for ApiConfig {
+    fn default() -> Self {
+        Self {
+            listen: "127.0.0.1:19180".to_string(),
+            api_key: None,
+        }
+    }
+}
+
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct WebhookConfig {
+    /// Shared secret used to verify signed webhook deliveries.
+    pub secret: Option<String>,
+    /// Only pushes to this branch trigger a deploy. Defaults to `git.branch`, then the checked out branch.
+    pub branch: Option<String>,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct GitConfig {
+    /// Remote that deploys are fetched from.
+    pub remote: String,
+    /// Branch that deploys track. Defaults to `GIT_BRANCH`, then the checked out branch.
+    pub branch: Option<String>,
+    /// How often the remote is checked for new commits, 0 disables polling.
+    pub poll_interval_secs: u64,
+}
+
+impl Default for GitConfig {
+    fn default() -> Self {
+        Self {
+            remote: "origin".to_string(),
+            branch: None,
+            poll_interval_secs: 0,
+        }
+    }
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct DeployConfig {
+    /// Wait until no update was requested for this long before deploying, 0 deploys right away.
+    pub debounce_secs: u64,
+    /// What happens once a new build is healthy.
+    pub policy: DeployPolicy,
+}
+
+impl Default for DeployConfig {
+    fn default() -> Self {
+        Self {
+            debounce_secs: 0,
+            policy: DeployPolicy::Auto,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum DeployPolicy {
+    /// Switch to a healthy new build right away.
+    Auto,
+    /// Keep a healthy new build in the standby slot until it is promoted.
+    Manual,
+}
+
+impl FromStr for DeployPolicy {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value {
+            "auto" => Ok(DeployPolicy::Auto),
+            "manual" => Ok(DeployPolicy::Manual),
+            other => Err(format!(
+                "unknown policy '{other}', expected 'auto' or 'manual'"
+            )),
+        }
+    }
+}
+
+/// Gradual cutover: the new instance first gets a share of the traffic that grows step by step.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct CanaryConfig {
+    pub enabled: bool,
+    /// Percentage of requests the new instance gets once it is healthy.
+    pub initial_percent: u8,
+    /// Added to the percentage after every interval, the switch completes at 100.
+    pub step_percent: u8,
+    pub step_interval_secs: u64,
+}
+
+impl Default for CanaryConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            initial_percent: 10,
+            step_percent: 20,
+            step_interval_secs: 60,
+        }
+    }
+}
+
+/// Switching back to the old instance when a new build fails too many requests after cutover.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct AutoRollbackConfig {
+    /// How long the old instance is kept running and the new one watched, 0 disables it.
+    pub window_secs: u64,
+    /// Share of 5xx responses and failed connects that triggers the switch back.
+    pub max_error_percent: u8,
+    /// Requests the new instance must have served before its error rate counts.
+    pub min_requests: u64,
+}
+
+impl Default for AutoRollbackConfig {
+    fn default() -> Self {
+        Self {
+            window_secs: 0,
+            max_error_percent: 20,
+            min_requests: 20,
+        }
+    }
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct PreviewConfig {
+    /// How long a token from `/_supervisor/preview` routes requests to the standby slot.
+    pub ttl_secs: u64,
+}
+
+impl Default for PreviewConfig {
+    fn default() -> Self {
+        Self { ttl_secs: 3600 }
+    }
+}
+
+/// Mirroring live GET requests to a new build before it goes live.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ShadowConfig {
+    /// Percentage of GET requests mirrored to the standby instance, 0 disables mirroring.
+    pub sample_percent: u8,
+    /// How long requests are mirrored before an automatic switch.
+    pub window_secs: u64,
+    /// Share of mirrored requests with a different status class that fails the deploy.
+    pub max_mismatch_percent: u8,
+    /// Mirrored requests needed before the mismatch share counts.
+    pub min_samples: u64,
+}
+
+impl Default for ShadowConfig {
+    fn default() -> Self {
+        Self {
+            sample_percent: 0,
+            window_secs: 60,
+            max_mismatch_percent: 5,
+            min_samples: 20,
+        }
+    }
+}
+
+/// Time limits for each deploy phase in seconds, 0 means no limit.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct TimeoutsConfig {
+    pub pull_secs: u64,
+    pub install_secs: u64,
+    pub build_secs: u64,
+    pub move_secs: u64,
+    /// Starting the new instance until it passes its health checks.
+    pub start_secs: u64,
+}
+
+impl Default for TimeoutsConfig {
+    fn default() -> Self {
+        Self {
+            pull_secs: 300,
+            install_secs: 1200,
+            build_secs: 1800,
+            move_secs: 300,
+            start_secs: 300,
+        }
+    }
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct InstancesConfig {
+    pub host: String,
+    pub instance1_port: u16,
+    pub instance2_port: u16,
+    /// Binary used to install, build and run the app, passed to the scripts as `SUPERVISOR_RUNTIME`.
+    pub runtime: String,
+    /// Server entry point, relative to the instance directory.
+    pub entry_point: String,
+}
+
+impl Default for InstancesConfig {
+    fn default() -> Self {
+        Self {
+            host: "127.0.0.1".to_string(),
+            instance1_port: 19131,
+            instance2_port: 19132,
+            runtime: "bun".to_string(),
+            entry_point: "server/index.mjs".to_string(),
+        }
+    }
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct PathsConfig {
+    /// Holds the git checkout, instance slots and releases.
+    pub app_dir: PathBuf,
+    pub scripts_dir: PathBuf,
+}
+
+impl Default for PathsConfig {
+    fn default() -> Self {
+        Self {
+            app_dir: PathBuf::from("/home/container/.app"),
+            scripts_dir: PathBuf::from("/usr/local/share/supervisor/scripts"),
+        }
+    }
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ReleasesConfig {
+    /// Number of past releases kept for rollbacks.
+    pub keep: usize,
+}
+
+impl Default for ReleasesConfig {
+    fn default() -> Self {
+        Self { keep: 5 }
+    }
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct HistoryConfig {
+    /// Number of finished deployments kept in the history file.
+    pub max_records: usize,
+}
+
+impl Default for HistoryConfig {
+    fn default() -> Self {
+        Self { max_records: 200 }
+    }
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct LogsConfig {
+    /// Number of per-deployment build logs kept on disk.
+    pub keep: usize,
+}
+
+impl Default for LogsConfig {
+    fn default() -> Self {
+        Self { keep: 20 }
+    }
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ShutdownConfig {
+    /// How long to wait for in-flight requests before stopping an old instance.
+    pub drain_timeout_secs: u64,
+    /// How long an instance may take to exit after SIGTERM before it is killed.
+    pub term_grace_secs: u64,
+}
+
+impl Default for ShutdownConfig {
+    fn default() -> Self {
+        Self {
+            drain_timeout_secs: 30,
+            term_grace_secs: 10,
+        }
+    }
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct RestartConfig {
+    /// Crashes within the window that are restarted before the slot is marked failed.
+    pub max_restarts: usize,
+    pub window_secs: u64,
+    /// Initial restart delay, doubled on each crash.
+    pub backoff_secs: u64,
+}
+
+impl Default for RestartConfig {
+    fn default() -> Self {
+        Self {
+            max_restarts: 5,
+            window_secs: 300,
+            backoff_secs: 1,
+        }
+    }
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct HealthConfig {
+    pub mode: HealthCheckMode,
+    pub path: String,
+    /// Accepted status codes, e.g. `200-299,304`.
+    pub status: String,
+    pub body_contains: Option<String>,
+    /// JSON field and its expected value, e.g. `status=ok`.
+    pub json_field: Option<String>,
+    pub timeout_secs: u64,
+    pub interval_secs: u64,
+    pub max_attempts: u32,
+}
+
+impl Default for HealthConfig {
+    fn default() -> Self {
+        Self {
+            mode: HealthCheckMode::Http,
+            path: "/".to_string(),
+            status: "200-399,400-405".to_string(),
+            body_contains: None,
+            json_field: None,
+            timeout_secs: 5,
+            interval_secs: 3,
+            max_attempts: 10,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum ConfigError {
+    Read {
+        path: PathBuf,
+        source: std::io::Error,
+    },
+    Parse {
+        path: PathBuf,
+        source: toml::de::Error,
+    },
+    Env {
+        name: &'static str,
+        value: String,
+        reason: String,
+    },
+    Invalid {
+        field: &'static str,
+        reason: String,
+    },
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConfigError::Read { path, source } => {
+                write!(
+                    f,
+                    "could not read config file {}: {}",
+                    path.display(),
+                    source
+                )
+            }
+            ConfigError::Parse { path, source } => {
+                write!(f, "invalid config file {}: {}", path.display(), source)
+            }
+            ConfigError::Env {
+                name,
+                value,
+                reason,
+            } => write!(f, "invalid value '{value}' for {name}: {reason}"),
+            ConfigError::Invalid { field, reason } => write!(f, "invalid {field}: {reason}"),
+        }
+    }
+}
+
+impl std::error::Error for ConfigError {}
+
+/// Loads, validates and installs the global config. Must run before anything reads it.
+pub fn init() -> Result<&'static Config, ConfigError> {
+    let config = Config::load()?;
+    Ok(CONFIG.get_or_init(|| config))
+}
+
+/// The global config; falls back to the defaults if `init` was never called.
+pub fn get() -> &'static Config {
+    CONFIG.get_or_init(Config::default)
+}
+
+impl Config {
+    /// Reads the file named by `SUPERVISOR_CONFIG` (or the default path, if present),
+    /// applies environment overrides and validates the result.
+    pub fn load() -> Result<Self, ConfigError> {
+        let explicit_path = std::env::var("SUPERVISOR_CONFIG").ok();
+        let path = PathBuf::from(explicit_path.as_deref().unwrap_or(DEFAULT_CONFIG_PATH));
+
+        // a missing file is only an error if it was asked for explicitly
+        let mut config = if explicit_path.is_some() || path.exists() {
+            Self::from_file(&path)?
+        } else {
+            Self::default()
+        };
+
+        config.apply_env_overrides()?;
+        config.validate()?;
+        Ok(config)
+    }
+
+    fn from_file(path: &Path) -> Result<Self, ConfigError> {
+        let contents = std::fs::read_to_string(path).map_err(|source| ConfigError::Read {
+            path: path.to_path_buf(),
+            source,
+        })?;
+        toml::from_str(&contents).map_err(|source| ConfigError::Parse {
+            path: path.to_path_buf(),
+            source,
+        })
+    }
+
+    fn apply_env_overrides(&mut self) -> Result<(), ConfigError> {
+        env_override("SUPERVISOR_PROXY_LISTEN", &mut self.proxy.listen)?;
+        env_override_opt_path(
+            "SUPERVISOR_UNAVAILABLE_PAGE",
+            &mut self.proxy.unavailable_page,
+        );
+        env_override(
+            "SUPERVISOR_RETRY_AFTER_SECS",
+            &mut self.proxy.retry_after_secs,
+        )?;
+        env_override("SUPERVISOR_API_LISTEN", &mut self.api.listen)?;
+        env_override_opt("SUPERVISOR_API_KEY", &mut self.api.api_key);
+        env_override_opt("SUPERVISOR_WEBHOOK_SECRET", &mut self.webhook.secret);
+        env_override_opt("SUPERVISOR_WEBHOOK_BRANCH", &mut self.webhook.branch);
+
+        env_override("SUPERVISOR_GIT_REMOTE", &mut self.git.remote)?;
+        env_override_opt("SUPERVISOR_GIT_BRANCH", &mut self.git.branch);
+        if self.git.branch.is_none() {
+            // the branch the container cloned is the one being deployed
+            env_override_opt("GIT_BRANCH", &mut self.git.branch);
+        }
+        env_override(
+            "SUPERVISOR_GIT_POLL_INTERVAL_SECS",
+            &mut self.git.poll_interval_secs,
+        )?;
+        if self.webhook.branch.is_none() {
+            self.webhook.branch = self.git.branch.clone();
+        }
+
+        env_override(
+            "SUPERVISOR_DEPLOY_DEBOUNCE_SECS",
+            &mut self.deploy.debounce_secs,
+        )?;
+        env_override("SUPERVISOR_DEPLOY_POLICY", &mut self.deploy.policy)?;
+
+        env_override("SUPERVISOR_CANARY_ENABLED", &mut self.canary.enabled)?;
+        env_override(
+            "SUPERVISOR_CANARY_INITIAL_PERCENT",
+            &mut self.canary.initial_percent,
+        )?;
+        env_override(
+            "SUPERVISOR_CANARY_STEP_PERCENT",
+            &mut self.canary.step_percent,
+        )?;
+        env_override(
+            "SUPERVISOR_CANARY_STEP_INTERVAL_SECS",
+            &mut self.canary.step_interval_secs,
+        )?;
+
+        env_override(
+            "SUPERVISOR_AUTO_ROLLBACK_WINDOW_SECS",
+            &mut self.auto_rollback.window_secs,
+        )?;
+        env_override(
+            "SUPERVISOR_AUTO_ROLLBACK_MAX_ERROR_PERCENT",
+            &mut self.auto_rollback.max_error_percent,
+        )?;
+        env_override(
+            "SUPERVISOR_AUTO_ROLLBACK_MIN_REQUESTS",
+            &mut self.auto_rollback.min_requests,
+        )?;
+
+        env_override("SUPERVISOR_PREVIEW_TTL_SECS", &mut self.preview.ttl_secs)?;
+
+        env_override(
+            "SUPERVISOR_SHADOW_SAMPLE_PERCENT",
+            &mut self.shadow.sample_percent,
+        )?;
+        env_override(
+            "SUPERVISOR_SHADOW_WINDOW_SECS",
+            &mut self.shadow.window_secs,
+        )?;
+        env_override(
+            "SUPERVISOR_SHADOW_MAX_MISMATCH_PERCENT",
+            &mut self.shadow.max_mismatch_percent,
+        )?;
+        env_override(
+            "SUPERVISOR_SHADOW_MIN_SAMPLES",
+            &mut self.shadow.min_samples,
+        )?;
+
+        env_override("SUPERVISOR_TIMEOUT_PULL_SECS", &mut self.timeouts.pull_secs)?;
+        env_override(
+            "SUPERVISOR_TIMEOUT_INSTALL_SECS",
+            &mut self.timeouts.install_secs,
+        )?;
+        env_override(
+            "SUPERVISOR_TIMEOUT_BUILD_SECS",
+            &mut self.timeouts.build_secs,
+        )?;
+        env_override("SUPERVISOR_TIMEOUT_MOVE_SECS", &mut self.timeouts.move_secs)?;
+        env_override(
+            "SUPERVISOR_TIMEOUT_START_SECS",
+            &mut self.timeouts.start_secs,
+        )?;
+
+        env_override("SUPERVISOR_INSTANCE_HOST", &mut self.instances.host)?;
+        env_override(
+            "SUPERVISOR_INSTANCE1_PORT",
+            &mut self.instances.instance1_port,
+        )?;
+        env_override(
+            "SUPERVISOR_INSTANCE2_PORT",
+            &mut self.instances.instance2_port,
+        )?;
+        env_override("SUPERVISOR_RUNTIME", &mut self.instances.runtime)?;
+        env_override("SUPERVISOR_ENTRY_POINT", &mut self.instances.entry_point)?;
+
+        env_override("SUPERVISOR_APP_DIR", &mut self.paths.app_dir)?;
+        env_override("SUPERVISOR_SCRIPTS_DIR", &mut self.paths.scripts_dir)?;
+
+        env_override("SUPERVISOR_KEEP_RELEASES", &mut self.releases.keep)?;
+        env_override(
+            "SUPERVISOR_HISTORY_MAX_RECORDS",
+            &mut self.history.max_records,
+        )?;
+        env_override("SUPERVISOR_DEPLOY_LOGS_KEEP", &mut self.logs.keep)?;
+
+        env_override(
+            "SUPERVISOR_DRAIN_TIMEOUT_SECS",
+            &mut self.shutdown.drain_timeout_secs,
+        )?;
+        env_override(
+            "SUPERVISOR_TERM_GRACE_SECS",
+            &mut self.shutdown.term_grace_secs,
+        )?;
+
+        env_override("SUPERVISOR_MAX_RESTARTS", &mut self.restart.max_restarts)?;
+        env_override(
+            "SUPERVISOR_RESTART_WINDOW_SECS",
+            &mut self.restart.window_secs,
+        )?;
+        env_override(
+            "SUPERVISOR_RESTART_BACKOFF_SECS",
+            &mut self.restart.backoff_secs,
+        )?;
+
+        env_override("SUPERVISOR_HEALTH_MODE", &mut self.health.mode)?;
+        env_override("SUPERVISOR_HEALTH_PATH", &mut self.health.path)?;
+        env_override("SUPERVISOR_HEALTH_STATUS", &mut self.health.status)?;
+        env_override_opt(
+            "SUPERVISOR_HEALTH_BODY_CONTAINS",
+            &mut self.health.body_contains,
+        );
+        env_override_opt("SUPERVISOR_HEALTH_JSON_FIELD", &mut self.health.json_field);
+        env_override(
+            "SUPERVISOR_HEALTH_TIMEOUT_SECS",
+            &mut self.health.timeout_secs,
+        )?;
+        env_override(
+            "SUPERVISOR_HEALTH_INTERVAL_SECS",
+            &mut self.health.interval_secs,
+        )?;
+        env_override(
+            "SUPERVISOR_HEALTH_MAX_ATTEMPTS",
+            &mut self.health.max_attempts,
+        )?;
+
+        Ok(())
+    }
+
+    fn validate(&self) -> Result<(), ConfigError> {
+        parse_socket_addr("proxy.listen", &self.proxy.listen)?;
+        if let Some(page) = &self.proxy.unavailable_page
+            && !page.is_file()
+        {
+            return Err(invalid(
+                "proxy.unavailable_page",
+                format!("{} is not a readable file", page.display()),
+            ));
+        }
+        parse_socket_addr("api.listen", &self.api.listen)?;
+
+        if self.git.remote.is_empty() || self.git.remote.starts_with('-') {
+            return Err(invalid("git.remote", "must be a remote name or URL"));
+        }
+        if let Some(branch) = &self.git.branch
+            && (branch.is_empty() || branch.starts_with('-'))
+        {
+            return Err(invalid("git.branch", "must be a branch name"));
+        }
+
+        if self.instances.instance1_port == 0 || self.instances.instance2_port == 0 {
+            return Err(invalid("instances ports", "ports must not be 0"));
+        }
+        if self.instances.instance1_port == self.instances.instance2_port {
+            return Err(invalid(
+                "instances ports",
+                "instance1_port and instance2_port must differ",
+            ));
+        }
+        for addr in [self.instance_backend("1"), self.instance_backend("2")] {
+            parse_socket_addr("instances.host", &addr)?;
+        }
+        if self.instances.runtime.trim().is_empty() {
+            return Err(invalid("instances.runtime", "must not be empty"));
+        }
+        if self.instances.entry_point.trim().is_empty() {
+            return Err(invalid("instances.entry_point", "must not be empty"));
+        }
+
+        if !self.paths.app_dir.is_absolute() {
+            return Err(invalid("paths.app_dir", "must be an absolute path"));
+        }
+        if !self.paths.scripts_dir.is_absolute() {
+            return Err(invalid("paths.scripts_dir", "must be an absolute path"));
+        }
+
+        if !(1..=100).contains(&self.canary.initial_percent) {
+            return Err(invalid(
+                "canary.initial_percent",
+                "must be between 1 and 100",
+            ));
+        }
+        if !(1..=100).contains(&self.canary.step_percent) {
+            return Err(invalid("canary.step_percent", "must be between 1 and 100"));
+        }
+        if self.canary.step_interval_secs == 0 {
+            return Err(invalid(
+                "canary.step_interval_secs",
+                "must be greater than 0",
+            ));
+        }
+
+        if !(1..=100).contains(&self.auto_rollback.max_error_percent) {
+            return Err(invalid(
+                "auto_rollback.max_error_percent",
+                "must be between 1 and 100",
+            ));
+        }
+        if self.auto_rollback.min_requests == 0 {
+            return Err(invalid(
+                "auto_rollback.min_requests",
+                "must be greater than 0",
+            ));
+        }
+
+        if self.preview.ttl_secs == 0 {
+            return Err(invalid("preview.ttl_secs", "must be greater than 0"));
+        }
+
+        if self.shadow.sample_percent > 100 {
+            return Err(invalid(
+                "shadow.sample_percent",
+                "must be between 0 and 100",
+            ));
+        }
+        if self.shadow.window_secs == 0 {
+            return Err(invalid("shadow.window_secs", "must be greater than 0"));
+        }
+        if self.shadow.max_mismatch_percent > 100 {
+            return Err(invalid(
+                "shadow.max_mismatch_percent",
+                "must be between 0 and 100",
+            ));
+        }
+        if self.shadow.min_samples == 0 {
+            return Err(invalid("shadow.min_samples", "must be greater than 0"));
+        }
+
+        if self.releases.keep == 0 {
+            return Err(invalid(
+                "releases.keep",
+                "at least one release must be kept",
+            ));
+        }
+        if self.history.max_records == 0 {
+            return Err(invalid(
+                "history.max_records",
+                "at least one record must be kept",
+            ));
+        }
+        if self.logs.keep == 0 {
+            return Err(invalid("logs.keep", "at least one log must be kept"));
+        }
+        if self.restart.window_secs == 0 {
+            return Err(invalid("restart.window_secs", "must be greater than 0"));
+        }
+
+        HealthCheckConfig::try_from(&self.health).map_err(|reason| ConfigError::Invalid {
+            field: "health",
+            reason,
+        })?;
+
+        Ok(())
+    }
+
+    /// Address an instance slot serves on, e.g. `127.0.0.1:19131`.
+    pub fn instance_backend(&self, instance_number: &str) -> String {
+        format!(
+            "{}:{}",
+            self.instances.host,
+            self.instance_port(instance_number)
+        )
+    }
+
+    pub fn instance_port(&self, instance_number: &str) -> u16 {
+        if instance_number == "1" {
+            self.instances.instance1_port
+        } else {
+            self.instances.instance2_port
+        }
+    }
+
+    /// Directory a slot's build is copied into.
+    pub fn instance_dir(&self, instance_number: &str) -> PathBuf {
+        self.paths.app_dir.join("instance").join(instance_number)
+    }
+
+    pub fn git_repo_dir(&self) -> PathBuf {
+        self.paths.app_dir.join("git-repo")
+    }
+
+    pub fn releases_dir(&self) -> PathBuf {
+        self.paths.app_dir.join("releases")
+    }
+
+    pub fn script(&self, name: &str) -> PathBuf {
+        self.paths.scripts_dir.join(name)
+    }
+
+    /// Environment passed to the shell scripts so they agree on the layout.
+    pub fn script_env(&self) -> Vec<(&'static str, String)> {
+        vec![
+            (
+                "SUPERVISOR_APP_DIR",
+                self.paths.app_dir.display().to_string(),
+            ),
+            (
+                "SUPERVISOR_SCRIPTS_DIR",
+                self.paths.scripts_dir.display().to_string(),
+            ),
+            ("SUPERVISOR_RUNTIME", self.instances.runtime.clone()),
+        ]
+    }
+
+    /// Parsed health check settings; validated when the config is loaded.
+    pub fn health_check(&self) -> HealthCheckConfig {
+        HealthCheckConfig::try_from(&self.health).unwrap_or_default()
+    }
+
+    /// Interval of the git remote poller, `None` if polling is disabled.
+    pub fn poll_interval(&self) -> Option<Duration> {
+        match self.git.poll_interval_secs {
+            0 => None,
+            secs => Some(Duration::from_secs(secs)),
+        }
+    }
+
+    /// How long update requests must settle before deploying, `None` if not debounced.
+    pub fn debounce(&self) -> Option<Duration> {
+        match self.deploy.debounce_secs {
+            0 => None,
+            secs => Some(Duration::from_secs(secs)),
+        }
+    }
+
+    /// Whether new builds wait in the standby slot until they are promoted.
+    pub fn manual_promotion(&self) -> bool {
+        self.deploy.policy == DeployPolicy::Manual
+    }
+
+    /// Whether live GET requests are mirrored to a new build before it goes live.
+    pub fn shadow_enabled(&self) -> bool {
+        self.shadow.sample_percent > 0
+    }
+
+    pub fn shadow_window(&self) -> Duration {
+        Duration::from_secs(self.shadow.window_secs)
+    }
+
+    pub fn canary_step_interval(&self) -> Duration {
+        Duration::from_secs(self.canary.step_interval_secs)
+    }
+
+    /// How long a new build is watched after cutover, `None` if auto rollback is off.
+    pub fn auto_rollback_window(&self) -> Option<Duration> {
+        match self.auto_rollback.window_secs {
+            0 => None,
+            secs => Some(Duration::from_secs(secs)),
+        }
+    }
+
+    pub fn drain_timeout(&self) -> Duration {
+        Duration::from_secs(self.shutdown.drain_timeout_secs)
+    }
+
+    pub fn term_grace(&self) -> Duration {
+        Duration::from_secs(self.shutdown.term_grace_secs)
+    }
+}
+
+fn env_override<T>(name: &'static str, target: &mut T) -> Result<(), ConfigError>
+where
+    T: FromStr,
+    T::Err: fmt::Display,
+{
+    let Ok(value) = std::env::var(name) else {
+        return Ok(());
+    };
+    *target = value.parse().map_err(|e: T::Err| ConfigError::Env {
+        name,
+        value: value.clone(),
+        reason: e.to_string(),
+    })?;
+    Ok(())
+}
+
+fn env_override_opt(name: &'static str, target: &mut Option<String>) {
+    if let Ok(value) = std::env::var(name) {
+        *target = Some(value).filter(|v| !v.is_empty());
+    }
+}
+
+fn env_override_opt_path(name: &'static str, target: &mut Option<PathBuf>) {
+    if let Ok(value) = std::env::var(name) {
+        *target = Some(value).filter(|v| !v.is_empty()).map(PathBuf::from);
+    }
+}
+
+fn parse_socket_addr(field: &'static str, value: &str) -> Result<SocketAddr, ConfigError> {
+    value
+        .parse()
+        .map_err(|e: std::net::AddrParseError| invalid(field, format!("'{value}': {e}")))
+}
+
+fn invalid(field: &'static str, reason: impl Into<String>) -> ConfigError {
+    ConfigError::Invalid {
+        field,
+        reason: reason.into(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(toml: &str) -> Config {
+        toml::from_str(toml).expect("valid toml")
+    }
+
+    /// The field a config is rejected for.
+    fn rejected_field(toml: &str) -> &'static str {
+        match parse(toml).validate() {
+            Err(ConfigError::Invalid { field, .. }) => field,
+            other => panic!("expected an invalid config, got {other:?}"),
+        }
+    }
+
+    #[test]
+    fn defaults_are_valid() {
+        assert!(Config::default().validate().is_ok());
+    }
+
+    #[test]
+    fn rejects_zero_limits() {
+        assert_eq!(rejected_field("[health]\ntimeout_secs = 0"), "health");
+        assert_eq!(rejected_field("[health]\nmax_attempts = 0"), "health");
+        assert_eq!(
+            rejected_field("[canary]\nstep_interval_secs = 0"),
+            "canary.step_interval_secs"
+        );
+        assert_eq!(
+            rejected_field("[preview]\nttl_secs = 0"),
+            "preview.ttl_secs"
+        );
+        assert_eq!(
+            rejected_field("[shadow]\nwindow_secs = 0"),
+            "shadow.window_secs"
+        );
+        assert_eq!(rejected_field("[releases]\nkeep = 0"), "releases.keep");
+        assert_eq!(rejected_field("[logs]\nkeep = 0"), "logs.keep");
+        assert_eq!(
+            rejected_field("[restart]\nwindow_secs = 0"),
+            "restart.window_secs"
+        );
+    }
+
+    #[test]
+    fn rejects_percentages_out_of_range() {
+        assert_eq!(
+            rejected_field("[canary]\ninitial_percent = 0"),
+            "canary.initial_percent"
+        );
+        assert_eq!(
+            rejected_field("[canary]\nstep_percent = 101"),
+            "canary.step_percent"
+        );
+        assert_eq!(
+            rejected_field("[auto_rollback]\nmax_error_percent = 101"),
+            "auto_rollback.max_error_percent"
+        );
+        assert_eq!(
+            rejected_field("[shadow]\nsample_percent = 101"),
+            "shadow.sample_percent"
+        );
+        assert_eq!(
+            rejected_field("[shadow]\nmax_mismatch_percent = 101"),
+            "shadow.max_mismatch_percent"
+        );
+    }
+
+    #[test]
+    fn rejects_bad_instances_paths_and_git_settings() {
+        assert_eq!(
+            rejected_field("[instances]\ninstance1_port = 19132"),
+            "instances ports"
+        );
+        assert_eq!(
+            rejected_field("[instances]\nruntime = \" \""),
+            "instances.runtime"
+        );
+        assert_eq!(
+            rejected_field("[paths]\napp_dir = \"relative\""),
+            "paths.app_dir"
+        );
+        assert_eq!(rejected_field("[git]\nbranch = \"-x\""), "git.branch");
+        assert_eq!(
+            rejected_field("[proxy]\nlisten = \"nowhere\""),
+            "proxy.listen"
+        );
+        assert_eq!(rejected_field("[health]\nstatus = \"299-200\""), "health");
+    }
+
+    #[test]
+    fn rejects_unknown_keys_and_values_when_parsing() {
+        assert!(toml::from_str::<Config>("[deploy]\npolicy = \"manul\"").is_err());
+        assert!(toml::from_str::<Config>("[health]\nmode = \"ftp\"").is_err());
+        assert!(toml::from_str::<Config>("[proxy]\nlistenn = \"0.0.0.0:80\"").is_err());
+    }
+
+    #[test]
+    fn webhook_secret_works_without_api_key() {
+        // provider deliveries are authenticated by their signature alone
+        let config = parse("[webhook]\nsecret = \"s3cret\"");
+        assert!(config.api.api_key.is_none());
+        assert!(config.validate().is_ok());
+    }
+
+    // the only test that touches the environment, so parallel tests never see its variables
+    #[test]
+    fn env_overrides_replace_file_values() {
+        let set = |name: &str, value: &str| {
+            // SAFETY: no other test reads or writes these variables
+            unsafe { std::env::set_var(name, value) }
+        };
+        let clear = |names: &[&str]| {
+            for name in names {
+                // SAFETY: see above
+                unsafe { std::env::remove_var(name) }
+            }
+        };
+        let names = [
+            "SUPERVISOR_CANARY_STEP_PERCENT",
+            "SUPERVISOR_DEPLOY_POLICY",
+            "SUPERVISOR_HEALTH_MODE",
+            "SUPERVISOR_API_KEY",
+            "SUPERVISOR_GIT_BRANCH",
+            "GIT_BRANCH",
+        ];
+
+        set("SUPERVISOR_CANARY_STEP_PERCENT", "25");
+        set("SUPERVISOR_DEPLOY_POLICY", "manual");
+        set("SUPERVISOR_HEALTH_MODE", "tcp");
+        set("SUPERVISOR_API_KEY", "");
+        set("GIT_BRANCH", "release");
+
+        let mut config = parse("[canary]\nstep_percent = 10\n[api]\napi_key = \"file-key\"");
+        let applied = config.apply_env_overrides();
+
+        set("SUPERVISOR_CANARY_STEP_PERCENT", "lots");
+        let mut invalid_config = Config::default();
+        let invalid = invalid_config.apply_env_overrides();
+        clear(&names);
+
+        assert!(applied.is_ok());
+        assert_eq!(config.canary.step_percent, 25);
+        assert_eq!(config.deploy.policy, DeployPolicy::Manual);
+        assert_eq!(config.health.mode, HealthCheckMode::Tcp);
+        // an empty value unsets optional settings
+        assert_eq!(config.api.api_key, None);
+        // the cloned branch is tracked and webhooks follow it
+        assert_eq!(config.git.branch.as_deref(), Some("release"));
+        assert_eq!(config.webhook.branch.as_deref(), Some("release"));
+
+        assert!(matches!(
+            invalid,
+            Err(ConfigError::Env {
+                name: "SUPERVISOR_CANARY_STEP_PERCENT",
+                ..
+            })
+        ));
+    }
+}
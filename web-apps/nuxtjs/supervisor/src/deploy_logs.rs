@@ -0,0 +1,93 @@
+use crate::config;
+use crate::utils::{self, LogFile};
+use std::io::Write;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+
+/// The log of the deployment job currently running. Jobs run one at a time.
+static CURRENT: Mutex<Option<(u64, LogFile)>> = Mutex::new(None);
+
+/// Creates the log file for a job, makes it the current one and prunes old logs.
+pub fn open(job_id: u64) {
+    let dir = dir();
+    let file = std::fs::create_dir_all(&dir)
+        .and_then(|_| std::fs::File::create(path(job_id)))
+        .map(|file| Arc::new(Mutex::new(file)));
+
+    match file {
+        Ok(file) => *CURRENT.lock().unwrap() = Some((job_id, file)),
+        Err(e) => eprintln!("Error creating log file for deployment {}: {}", job_id, e),
+    }
+
+    prune(config::get().logs.keep);
+}
+
+/// Stops copying output into the job's log.
+pub fn close(job_id: u64) {
+    let mut current = CURRENT.lock().unwrap();
+    if current.as_ref().is_some_and(|(id, _)| *id == job_id) {
+        *current = None;
+    }
+}
+
+/// Log file of the running job, passed to the commands it runs.
+pub fn current() -> Option<LogFile> {
+    let current = CURRENT.lock().unwrap();
+    current.as_ref().map(|(_, file)| file.clone())
+}
+
+/// Writes a supervisor message into the job's log, if it is the running job.
+pub fn note(job_id: u64, message: &str) {
+    let file = {
+        let current = CURRENT.lock().unwrap();
+        match current.as_ref() {
+            Some((id, file)) if *id == job_id => file.clone(),
+            _ => return,
+        }
+    };
+
+    if let Ok(mut file) = file.lock() {
+        let _ = writeln!(file, "[supervisor] {} {}", utils::unix_timestamp(), message);
+    }
+}
+
+/// Contents of a job's log, `None` if it was never written or has been pruned.
+pub fn read(job_id: u64) -> Option<String> {
+    std::fs::read_to_string(path(job_id)).ok()
+}
+
+/// Deletes the oldest logs so at most `keep` remain.
+fn prune(keep: usize) {
+    let Ok(entries) = std::fs::read_dir(dir()) else {
+        return;
+    };
+
+    let mut job_ids: Vec<u64> = entries
+        .filter_map(|entry| entry.ok())
+        .filter_map(|entry| {
+            let name = entry.file_name();
+            let name = name.to_str()?;
+            name.strip_prefix("deploy-")?
+                .strip_suffix(".log")?
+                .parse()
+                .ok()
+        })
+        .collect();
+
+    if job_ids.len() <= keep {
+        return;
+    }
+
+    job_ids.sort_unstable();
+    for job_id in &job_ids[..job_ids.len() - keep] {
+        let _ = std::fs::remove_file(path(*job_id));
+    }
+}
+
+fn dir() -> PathBuf {
+    config::get().paths.app_dir.join("logs")
+}
+
+fn path(job_id: u64) -> PathBuf {
+    dir().join(format!("deploy-{job_id}.log"))
+}
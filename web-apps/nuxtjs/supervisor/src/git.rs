@@ -0,0 +1,206 @@
+use crate::config;
+use crate::deploy_logs;
+use crate::utils::{self, CommandError};
+use std::fmt;
+use tokio::process::Command;
+
+/// Why the app's git checkout could not be brought to the requested commit.
+#[derive(Debug)]
+pub enum GitError {
+    /// A git command failed to spawn or exited unsuccessfully.
+    Command(CommandError),
+    /// No branch is configured and the checkout is not on one.
+    NoBranch,
+    /// The requested ref does not look like a branch, tag or commit.
+    InvalidRef(String),
+    /// The requested ref could not be resolved after fetching.
+    RefNotFound(String),
+}
+
+impl fmt::Display for GitError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            GitError::Command(source) => write!(f, "{source}"),
+            GitError::NoBranch => write!(f, "no git branch configured and HEAD is detached"),
+            GitError::InvalidRef(git_ref) => write!(f, "invalid git ref '{git_ref}'"),
+            GitError::RefNotFound(git_ref) => write!(f, "git ref '{git_ref}' was not found"),
+        }
+    }
+}
+
+impl std::error::Error for GitError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            GitError::Command(source) => Some(source),
+            _ => None,
+        }
+    }
+}
+
+impl From<CommandError> for GitError {
+    fn from(source: CommandError) -> Self {
+        GitError::Command(source)
+    }
+}
+
+/// Where a sync moved the checkout.
+#[derive(Clone, Debug)]
+pub struct SyncResult {
+    pub before: Option<String>,
+    pub after: String,
+    /// The new commit does not descend from the old one, e.g. after a force-push.
+    pub force_pushed: bool,
+}
+
+/// Fetches the configured remote and hard resets the checkout to `git_ref`,
+/// or to the remote head of the tracked branch when no ref is given.
+pub async fn sync(git_ref: Option<&str>) -> Result<SyncResult, GitError> {
+    let config = config::get();
+    let remote = config.git.remote.as_str();
+
+    if let Some(git_ref) = git_ref
+        && (git_ref.is_empty() || git_ref.starts_with('-'))
+    {
+        return Err(GitError::InvalidRef(git_ref.to_string()));
+    }
+
+    let branch = tracked_branch().await?;
+    let tracking_ref = format!("refs/remotes/{remote}/{branch}");
+
+    let before = head_commit().await;
+
+    // the explicit refspec also works for clones made with --single-branch
+    let refspec = format!("+refs/heads/{branch}:{tracking_ref}");
+    run_git(&["fetch", "--prune", "--tags", "--force", remote, &refspec]).await?;
+
+    let target = match git_ref {
+        None => tracking_ref,
+        Some(git_ref) => {
+            // other branches and unadvertised commits are fetched on demand
+            match run_git(&["fetch", "--force", remote, git_ref]).await {
+                Ok(()) => "FETCH_HEAD".to_string(),
+                Err(e) => {
+                    tracing::warn!(
+                        target: "supervisor",
+                        git_ref,
+                        error = %e,
+                        "could not fetch ref, resolving it locally"
+                    );
+                    git_ref.to_string()
+                }
+            }
+        }
+    };
+
+    let commit_spec = format!("{target}^{{commit}}");
+    let after = git_output(&["rev-parse", "--verify", "--quiet", &commit_spec])
+        .await
+        .ok()
+        .flatten()
+        .ok_or_else(|| GitError::RefNotFound(git_ref.unwrap_or(&branch).to_string()))?;
+
+    let force_pushed = match before.as_deref() {
+        Some(before) if before != after => !is_ancestor(before, &after).await,
+        _ => false,
+    };
+
+    run_git(&["reset", "--hard", &after]).await?;
+    run_git(&["clean", "-fd"]).await?;
+
+    tracing::info!(
+        target: "supervisor",
+        before = before.as_deref().unwrap_or_default(),
+        after = %after,
+        force_pushed,
+        "git checkout synced"
+    );
+
+    Ok(SyncResult {
+        before,
+        after,
+        force_pushed,
+    })
+}
+
+/// Returns the commit the tracked branch points at on the remote, without fetching.
+pub async fn remote_head() -> Result<String, GitError> {
+    let remote = config::get().git.remote.as_str();
+    let branch = tracked_branch().await?;
+    let head_ref = format!("refs/heads/{branch}");
+
+    let output = git_output(&["ls-remote", "--exit-code", remote, &head_ref]).await?;
+    output
+        .as_deref()
+        .and_then(|line| line.split_whitespace().next())
+        .map(str::to_string)
+        .ok_or(GitError::RefNotFound(branch))
+}
+
+/// Returns the commit currently checked out in the app's git repository.
+pub async fn head_commit() -> Option<String> {
+    git_output(&["rev-parse", "HEAD"]).await.ok().flatten()
+}
+
+/// The configured branch, or the checked out one when none is set.
+pub async fn tracked_branch() -> Result<String, GitError> {
+    match &config::get().git.branch {
+        Some(branch) => Ok(branch.clone()),
+        None => current_branch().await.ok_or(GitError::NoBranch),
+    }
+}
+
+async fn current_branch() -> Option<String> {
+    git_output(&["symbolic-ref", "--quiet", "--short", "HEAD"])
+        .await
+        .ok()
+        .flatten()
+}
+
+async fn is_ancestor(ancestor: &str, descendant: &str) -> bool {
+    run_git(&["merge-base", "--is-ancestor", ancestor, descendant])
+        .await
+        .is_ok()
+}
+
+/// Runs git in the app's repository with its output streamed to the logs
+/// and into the running deployment's log file.
+async fn run_git(args: &[&str]) -> Result<(), CommandError> {
+    let repo_dir = config::get().git_repo_dir();
+    let repo_dir = repo_dir.to_string_lossy();
+    let mut full_args = vec!["-C", repo_dir.as_ref()];
+    full_args.extend_from_slice(args);
+
+    utils::run_cmd_with_log_file("git", &full_args, &[], deploy_logs::current())?
+        .wait()
+        .await
+}
+
+/// Runs git in the app's repository and returns its trimmed stdout, `None` if empty.
+async fn git_output(args: &[&str]) -> Result<Option<String>, CommandError> {
+    let repo_dir = config::get().git_repo_dir();
+    let cmd = format!("git {}", args.first().copied().unwrap_or_default());
+    let output = Command::new("git")
+        .arg("-C")
+        .arg(&repo_dir)
+        .args(args)
+        .output()
+        .await
+        .map_err(|source| CommandError::Spawn {
+            cmd: cmd.clone(),
+            source,
+        })?;
+
+    if !output.status.success() {
+        return Err(CommandError::Exit {
+            cmd,
+            status: output.status,
+        });
+    }
+
+    let stdout = String::from_utf8_lossy(&output.stdout).trim().to_string();
+    Ok(if stdout.is_empty() {
+        None
+    } else {
+        Some(stdout)
+    })
+}
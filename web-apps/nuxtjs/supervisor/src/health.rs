@@ -0,0 +1,238 @@
+use crate::config;
+use serde::Deserialize;
+use std::ops::RangeInclusive;
+use std::str::FromStr;
+use std::time::Duration;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum HealthCheckMode {
+    /// Send an HTTP request and inspect the response.
+    Http,
+    /// Only check that the port accepts TCP connections.
+    Tcp,
+}
+
+impl FromStr for HealthCheckMode {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value {
+            "http" => Ok(HealthCheckMode::Http),
+            "tcp" => Ok(HealthCheckMode::Tcp),
+            other => Err(format!("unknown mode '{other}', expected 'http' or 'tcp'")),
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct HealthCheckConfig {
+    pub mode: HealthCheckMode,
+    pub path: String,
+    pub accepted_statuses: Vec<RangeInclusive<u16>>,
+    pub body_contains: Option<String>,
+    /// Dotted JSON field path and the value it must have, e.g. `status` = `ok`.
+    pub json_field: Option<(String, String)>,
+    pub timeout: Duration,
+    pub interval: Duration,
+    pub max_attempts: u32,
+}
+
+impl Default for HealthCheckConfig {
+    fn default() -> Self {
+        Self {
+            mode: HealthCheckMode::Http,
+            path: "/".to_string(),
+            accepted_statuses: vec![200..=399, 400..=405],
+            body_contains: None,
+            json_field: None,
+            timeout: Duration::from_secs(5),
+            interval: Duration::from_secs(3),
+            max_attempts: 10,
+        }
+    }
+}
+
+impl TryFrom<&config::HealthConfig> for HealthCheckConfig {
+    type Error = String;
+
+    fn try_from(settings: &config::HealthConfig) -> Result<Self, Self::Error> {
+        if !settings.path.starts_with('/') {
+            return Err(format!("path '{}' must start with '/'", settings.path));
+        }
+
+        let accepted_statuses = parse_status_ranges(&settings.status)
+            .filter(|ranges| !ranges.is_empty())
+            .ok_or_else(|| format!("invalid status list '{}'", settings.status))?;
+
+        let json_field = match &settings.json_field {
+            Some(value) => {
+                let (field, expected) = value
+                    .split_once('=')
+                    .ok_or_else(|| format!("json_field '{value}' must look like 'field=value'"))?;
+                Some((field.trim().to_string(), expected.trim().to_string()))
+            }
+            None => None,
+        };
+
+        if settings.max_attempts == 0 {
+            return Err("max_attempts must be greater than 0".to_string());
+        }
+        if settings.timeout_secs == 0 {
+            return Err("timeout_secs must be greater than 0".to_string());
+        }
+
+        Ok(Self {
+            mode: settings.mode,
+            path: settings.path.clone(),
+            accepted_statuses,
+            body_contains: settings.body_contains.clone(),
+            json_field,
+            timeout: Duration::from_secs(settings.timeout_secs),
+            interval: Duration::from_secs(settings.interval_secs),
+            max_attempts: settings.max_attempts,
+        })
+    }
+}
+
+/// Parses a list like `200-299,301,400-405` into status code ranges.
+/// Reversed ranges like `299-200` are rejected, they would never match.
+pub fn parse_status_ranges(value: &str) -> Option<Vec<RangeInclusive<u16>>> {
+    value
+        .split(',')
+        .map(str::trim)
+        .filter(|part| !part.is_empty())
+        .map(|part| match part.split_once('-') {
+            Some((start, end)) => {
+                let start: u16 = start.trim().parse().ok()?;
+                let end: u16 = end.trim().parse().ok()?;
+                (start <= end).then_some(start..=end)
+            }
+            None => {
+                let code = part.parse().ok()?;
+                Some(code..=code)
+            }
+        })
+        .collect()
+}
+
+/// Polls the backend until it is healthy or the attempts run out.
+pub async fn wait_until_healthy(addr: &str, config: &HealthCheckConfig) -> bool {
+    for attempt in 1..=config.max_attempts {
+        tokio::time::sleep(config.interval).await;
+
+        match check(addr, config).await {
+            Ok(()) => return true,
+            Err(reason) => {
+                tracing::debug!(
+                    target: "supervisor",
+                    backend = addr,
+                    attempt,
+                    "health check failed: {reason}"
+                );
+            }
+        }
+    }
+    false
+}
+
+/// Runs a single health check against the backend, returning why it failed.
+pub async fn check(addr: &str, config: &HealthCheckConfig) -> Result<(), String> {
+    match config.mode {
+        HealthCheckMode::Tcp => {
+            match tokio::time::timeout(config.timeout, tokio::net::TcpStream::connect(addr)).await {
+                Ok(Ok(_)) => Ok(()),
+                Ok(Err(e)) => Err(format!("connect failed: {e}")),
+                Err(_) => Err("connect timed out".to_string()),
+            }
+        }
+        HealthCheckMode::Http => check_http(addr, config).await,
+    }
+}
+
+async fn check_http(addr: &str, config: &HealthCheckConfig) -> Result<(), String> {
+    let client = reqwest::Client::builder()
+        .timeout(config.timeout)
+        .build()
+        .map_err(|e| e.to_string())?;
+
+    let url = format!("http://{}{}", addr, config.path);
+    let response = client.get(&url).send().await.map_err(|e| e.to_string())?;
+
+    let status = response.status().as_u16();
+    if !config
+        .accepted_statuses
+        .iter()
+        .any(|range| range.contains(&status))
+    {
+        return Err(format!("unexpected status {status}"));
+    }
+
+    if config.body_contains.is_none() && config.json_field.is_none() {
+        return Ok(());
+    }
+
+    let body = response.text().await.map_err(|e| e.to_string())?;
+
+    if let Some(needle) = &config.body_contains
+        && !body.contains(needle.as_str())
+    {
+        return Err(format!("response body does not contain '{needle}'"));
+    }
+
+    if let Some((field, expected)) = &config.json_field {
+        let json: serde_json::Value =
+            serde_json::from_str(&body).map_err(|e| format!("invalid JSON body: {e}"))?;
+        let value = field
+            .split('.')
+            .try_fold(&json, |value, key| value.get(key))
+            .ok_or_else(|| format!("JSON field '{field}' is missing"))?;
+        let actual = match value {
+            serde_json::Value::String(s) => s.clone(),
+            other => other.to_string(),
+        };
+        if &actual != expected {
+            return Err(format!(
+                "JSON field '{field}' is '{actual}', expected '{expected}'"
+            ));
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_single_codes_and_ranges() {
+        assert_eq!(
+            parse_status_ranges("200-299,301,400-405"),
+            Some(vec![200..=299, 301..=301, 400..=405])
+        );
+    }
+
+    #[test]
+    fn ignores_whitespace_and_empty_parts() {
+        assert_eq!(
+            parse_status_ranges(" 200 - 204 , 304 ,,"),
+            Some(vec![200..=204, 304..=304])
+        );
+    }
+
+    #[test]
+    fn rejects_invalid_input() {
+        assert_eq!(parse_status_ranges("ok"), None);
+        assert_eq!(parse_status_ranges("200-"), None);
+        assert_eq!(parse_status_ranges("200-abc"), None);
+        assert_eq!(parse_status_ranges("70000"), None);
+    }
+
+    #[test]
+    fn rejects_reversed_ranges() {
+        assert_eq!(parse_status_ranges("299-200"), None);
+        assert_eq!(parse_status_ranges("200-299,405-400"), None);
+        assert_eq!(parse_status_ranges("204-204"), Some(vec![204..=204]));
+    }
+}
@@ -0,0 +1,125 @@
+use crate::config;
+use crate::instance_handler::{DeploymentJob, JobOutcome};
+use std::io::Write;
+use std::path::PathBuf;
+
+/// Finished deployment jobs, oldest first. Unreadable lines are skipped.
+pub fn list() -> Vec<DeploymentJob> {
+    let Ok(contents) = std::fs::read_to_string(path()) else {
+        return Vec::new();
+    };
+
+    contents
+        .lines()
+        .filter(|line| !line.trim().is_empty())
+        .filter_map(|line| serde_json::from_str(line).ok())
+        .collect()
+}
+
+/// Appends a finished job and drops the oldest records beyond the configured limit.
+pub fn append(job: &DeploymentJob) {
+    let mut records = list();
+    records.push(job.clone());
+
+    let keep = config::get().history.max_records;
+    if records.len() > keep {
+        records.drain(..records.len() - keep);
+    }
+
+    if let Err(e) = write(&records) {
+        eprintln!("Error writing deployment history: {}", e);
+    }
+}
+
+/// The recorded job with this id, if it is still in the history.
+pub fn find(id: u64) -> Option<DeploymentJob> {
+    list().into_iter().rev().find(|job| job.id == id)
+}
+
+/// The ref pinned by the last job that put a build live, `None` if it followed the tracked branch.
+/// Rollbacks pin the commit they went back to.
+pub fn deployed_ref() -> Option<String> {
+    pinned_ref(list())
+}
+
+fn pinned_ref(records: Vec<DeploymentJob>) -> Option<String> {
+    records
+        .into_iter()
+        .rev()
+        .find(|job| job.outcome == Some(JobOutcome::Succeeded) && job.live_instance.is_some())
+        .and_then(|job| job.git_ref)
+}
+
+/// Highest job id on record, so ids stay unique across restarts.
+pub fn last_id() -> u64 {
+    list().iter().map(|job| job.id).max().unwrap_or(0)
+}
+
+fn write(records: &[DeploymentJob]) -> std::io::Result<()> {
+    let path = path();
+    let tmp_path = path.with_extension("jsonl.tmp");
+
+    // write a copy and rename it, so a crash never leaves a half written history
+    let mut file = std::fs::File::create(&tmp_path)?;
+    for record in records {
+        let line = serde_json::to_string(record).map_err(std::io::Error::other)?;
+        writeln!(file, "{line}")?;
+    }
+    file.sync_all()?;
+
+    std::fs::rename(tmp_path, path)
+}
+
+fn path() -> PathBuf {
+    config::get().paths.app_dir.join("deployments.jsonl")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn record(id: u64, kind: &str, git_ref: Option<&str>, outcome: &str) -> DeploymentJob {
+        serde_json::from_value(serde_json::json!({
+            "id": id,
+            "kind": kind,
+            "trigger": "api",
+            "git_ref": git_ref,
+            "commit": git_ref,
+            "force_pushed": false,
+            "merged_requests": 0,
+            "phase": "finished",
+            "queued_at": id,
+            "outcome": outcome,
+            "phases": [],
+            "live_instance": "1",
+        }))
+        .expect("valid record")
+    }
+
+    #[test]
+    fn rollback_stays_pinned_across_restarts() {
+        let records = vec![
+            record(1, "update", None, "succeeded"),
+            record(2, "rollback", Some("c2"), "succeeded"),
+        ];
+        assert_eq!(pinned_ref(records).as_deref(), Some("c2"));
+    }
+
+    #[test]
+    fn update_without_ref_unpins() {
+        let records = vec![
+            record(1, "rollback", Some("c2"), "succeeded"),
+            record(2, "update", None, "succeeded"),
+        ];
+        assert_eq!(pinned_ref(records), None);
+    }
+
+    #[test]
+    fn failed_jobs_do_not_change_the_pin() {
+        let records = vec![
+            record(1, "rollback", Some("c2"), "succeeded"),
+            record(2, "update", None, "failed"),
+        ];
+        assert_eq!(pinned_ref(records).as_deref(), Some("c2"));
+    }
+}
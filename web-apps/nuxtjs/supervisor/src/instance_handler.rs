@@ -1,10 +1,29 @@
+use crate::build_info::{self, BuildInfo};
+use crate::config;
+use crate::deploy_logs;
+use crate::git::{self, GitError};
+use crate::health;
+use crate::history;
 use crate::proxy;
-use crate::utils;
+use crate::releases;
+use crate::shadow::{self, ShadowSummary};
+use crate::utils::{self, CommandError};
 use once_cell::sync::Lazy;
-use std::collections::VecDeque;
-use std::io::Error;
-use std::sync::RwLock;
-use tokio::sync::oneshot;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, VecDeque};
+use std::fmt;
+use std::future::Future;
+use std::os::unix::process::ExitStatusExt;
+use std::sync::{Arc, RwLock};
+use std::time::{Duration, Instant};
+use tokio::sync::{Notify, oneshot};
+
+// how many finished jobs are kept around for the job status endpoint
+const MAX_TRACKED_JOBS: usize = 100;
+// upper bound for the delay between automatic restarts of a crashed instance
+const MAX_RESTART_BACKOFF: Duration = Duration::from_secs(60);
+// how often the error rate of a new build is checked after cutover
+const ERROR_RATE_CHECK_INTERVAL: Duration = Duration::from_secs(5);
 
 struct AppState {
     current_main_instance: String,
@@ -12,6 +31,60 @@ struct AppState {
     instance2_proc: Option<utils::CommandHandle>,
     update_in_progress: bool,
     queued_update_waiters: VecDeque<oneshot::Sender<()>>,
+    next_job_id: u64,
+    jobs: HashMap<u64, DeploymentJob>,
+    job_order: VecDeque<u64>,
+    deployed_commit: Option<String>,
+    deployed_ref: Option<String>,
+    last_deploy: Option<DeploymentJob>,
+    slot_watches: HashMap<String, SlotWatch>,
+    pending_update: Option<PendingUpdate>,
+    job_waiters: HashMap<u64, Vec<oneshot::Sender<()>>>,
+    running_job: Option<RunningJob>,
+    /// Deploy script currently running, it leads its own process group.
+    deploy_script_pid: Option<u32>,
+    candidate: Option<Candidate>,
+}
+
+/// The update or rollback currently holding the queue, tracked so it can be cancelled.
+struct RunningJob {
+    job_id: u64,
+    cancel_requested: bool,
+    cancel: Arc<Notify>,
+}
+
+/// A healthy new instance that is not live yet, waiting for a promote or being canaried.
+struct Candidate {
+    job_id: u64,
+    instance: String,
+    /// Share of the requests it receives while canaried, `None` while it waits for a promote.
+    canary_percent: Option<u8>,
+    decision: Option<CandidateDecision>,
+    /// Wakes the waiting job when a decision is made.
+    signal: Arc<Notify>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum CandidateDecision {
+    Promote,
+    Discard,
+}
+
+/// The one update waiting behind the running deploy, later requests are merged into it.
+struct PendingUpdate {
+    job_id: u64,
+    git_ref: Option<String>,
+    expected_commit: Option<String>,
+    force: bool,
+    last_requested_at: Instant,
+}
+
+/// Crash bookkeeping for one instance slot.
+#[derive(Default)]
+struct SlotWatch {
+    recent_crashes: VecDeque<Instant>,
+    last_exit: Option<InstanceExit>,
+    failed: bool,
 }
 
 static STATE: Lazy<RwLock<AppState>> = Lazy::new(|| {
@@ -21,341 +94,2027 @@ static STATE: Lazy<RwLock<AppState>> = Lazy::new(|| {
         instance2_proc: None,
         update_in_progress: false,
         queued_update_waiters: VecDeque::new(),
+        // ids continue after the recorded history, so they stay unique across restarts
+        next_job_id: history::last_id() + 1,
+        jobs: HashMap::new(),
+        job_order: VecDeque::new(),
+        deployed_commit: None,
+        deployed_ref: None,
+        last_deploy: history::list()
+            .into_iter()
+            .rev()
+            .find(|job| job.outcome.is_some_and(JobOutcome::is_deploy)),
+        slot_watches: HashMap::new(),
+        pending_update: None,
+        job_waiters: HashMap::new(),
+        running_job: None,
+        deploy_script_pid: None,
+        candidate: None,
     })
 });
 
-#[derive(Clone, Debug)]
-pub struct InstanceStatus {
-    pub current_main_instance: Option<String>,
-    pub instance1_running: bool,
-    pub instance2_running: bool,
-    pub update_in_progress: bool,
-    pub queued_update_requests: usize,
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum JobPhase {
+    Queued,
+    PullingChanges,
+    InstallingDependencies,
+    Building,
+    MovingBuild,
+    StartingInstance,
+    HealthChecking,
+    /// Live GET requests are mirrored to the new instance to compare its answers.
+    Shadowing,
+    /// The new instance is healthy and waits in the standby slot for a promote.
+    AwaitingPromotion,
+    /// The new instance serves part of the traffic next to the old one.
+    Canary,
+    SwitchingBackend,
+    /// The new instance serves all traffic while the old one is kept as a fallback.
+    Monitoring,
+    CleaningUp,
+    Finished,
 }
 
-pub struct InstanceHandler {}
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum JobKind {
+    Startup,
+    Update,
+    Rollback,
+}
 
-impl InstanceHandler {
-    pub async fn startup() {
-        let pull_latest_git_changes_proc = utils::run_cmd_with_logs(
-            "/usr/local/share/supervisor/scripts/pull_latest_git_changes.sh",
-            &[],
-            &[],
-        );
-        if let Err(e) = pull_latest_git_changes_proc.wait().await {
-            eprintln!("Error pulling latest git changes: {}", e);
-            // continue startup even if git pull fails
+/// What asked for a deployment job.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Trigger {
+    Startup,
+    /// A git provider push webhook.
+    Webhook,
+    /// A plain API request.
+    Api,
+    Cli,
+    /// The git remote poller.
+    Poll,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum JobOutcome {
+    Succeeded,
+    Failed,
+    Cancelled,
+    /// The requested commit was already deployed, so nothing was rebuilt.
+    Skipped,
+}
+
+impl JobPhase {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            JobPhase::Queued => "queued",
+            JobPhase::PullingChanges => "pulling_changes",
+            JobPhase::InstallingDependencies => "installing_dependencies",
+            JobPhase::Building => "building",
+            JobPhase::MovingBuild => "moving_build",
+            JobPhase::StartingInstance => "starting_instance",
+            JobPhase::HealthChecking => "health_checking",
+            JobPhase::Shadowing => "shadowing",
+            JobPhase::AwaitingPromotion => "awaiting_promotion",
+            JobPhase::Canary => "canary",
+            JobPhase::SwitchingBackend => "switching_backend",
+            JobPhase::Monitoring => "monitoring",
+            JobPhase::CleaningUp => "cleaning_up",
+            JobPhase::Finished => "finished",
         }
+    }
+}
 
-        let cleanup_instances_result: Result<(), Error> = Self::cleanup_instances().await;
-        if let Err(e) = cleanup_instances_result {
-            eprintln!("Error cleaning up instances: {}", e);
-            // continue startup even if cleanup fails
+impl JobOutcome {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            JobOutcome::Succeeded => "succeeded",
+            JobOutcome::Failed => "failed",
+            JobOutcome::Cancelled => "cancelled",
+            JobOutcome::Skipped => "skipped",
         }
+    }
 
-        let create_new_build_proc = utils::run_cmd_with_logs(
-            "/usr/local/share/supervisor/scripts/create_new_build.sh",
-            &[],
-            &[],
-        );
-        if let Err(e) = create_new_build_proc.wait().await {
-            eprintln!("Error creating new build: {}", e);
-            return;
+    /// Whether the job actually tried to deploy something.
+    pub fn is_deploy(self) -> bool {
+        !matches!(self, JobOutcome::Cancelled | JobOutcome::Skipped)
+    }
+}
+
+impl JobKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            JobKind::Startup => "startup",
+            JobKind::Update => "update",
+            JobKind::Rollback => "rollback",
         }
+    }
+}
 
-        let move_build_to_instance_proc = utils::run_cmd_with_logs(
-            "/usr/local/share/supervisor/scripts/move_build_to_instance.sh",
-            &["1"],
-            &[],
-        );
-        if let Err(e) = move_build_to_instance_proc.wait().await {
-            eprintln!("Error moving build to instance 1: {}", e);
-            return;
+impl Trigger {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Trigger::Startup => "startup",
+            Trigger::Webhook => "webhook",
+            Trigger::Api => "api",
+            Trigger::Cli => "cli",
+            Trigger::Poll => "poll",
         }
+    }
+}
+
+/// How long a job spent in one phase.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct PhaseTiming {
+    pub phase: JobPhase,
+    pub started_at_ms: u64,
+    /// Unset while the phase is still running.
+    pub duration_ms: Option<u64>,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct DeploymentJob {
+    pub id: u64,
+    pub kind: JobKind,
+    /// What requested the job, the latest request if several were merged.
+    pub trigger: Trigger,
+    /// The branch, tag or commit that was requested, if not the tracked branch.
+    pub git_ref: Option<String>,
+    /// The commit a push webhook announced for the tracked branch.
+    pub expected_commit: Option<String>,
+    /// The commit checked out before this job fetched, for rollbacks the one that was deployed.
+    pub previous_commit: Option<String>,
+    pub commit: Option<String>,
+    pub force_pushed: bool,
+    /// Update requests merged into this job while it was waiting.
+    pub merged_requests: usize,
+    pub phase: JobPhase,
+    pub queued_at: u64,
+    pub started_at: Option<u64>,
+    pub finished_at: Option<u64>,
+    pub outcome: Option<JobOutcome>,
+    pub error: Option<String>,
+    pub phases: Vec<PhaseTiming>,
+    /// The slot this job put live, if it got that far.
+    pub live_instance: Option<String>,
+    /// How the new build answered mirrored requests before the switch, if they were mirrored.
+    pub shadow: Option<ShadowSummary>,
+}
 
+impl DeploymentJob {
+    /// Closes the timing of the current phase and starts timing `phase`.
+    fn enter_phase(&mut self, phase: JobPhase) {
+        let now = utils::unix_timestamp_millis();
+        if let Some(current) = self.phases.last_mut()
+            && current.duration_ms.is_none()
         {
-            let mut state = STATE.write().unwrap();
-            state.current_main_instance = "1".to_string();
+            current.duration_ms = Some(now.saturating_sub(current.started_at_ms));
         }
+        if phase != JobPhase::Finished {
+            self.phases.push(PhaseTiming {
+                phase,
+                started_at_ms: now,
+                duration_ms: None,
+            });
+        }
+        self.phase = phase;
+    }
+}
+
+/// How an instance process exited on its own.
+#[derive(Clone, Debug, Serialize)]
+pub struct InstanceExit {
+    pub code: Option<i32>,
+    pub signal: Option<i32>,
+    pub exited_at: u64,
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct InstanceStatus {
+    pub current_main_instance: Option<String>,
+    pub instance1_running: bool,
+    pub instance2_running: bool,
+    pub instance1_failed: bool,
+    pub instance2_failed: bool,
+    pub instance1_last_exit: Option<InstanceExit>,
+    pub instance2_last_exit: Option<InstanceExit>,
+    pub update_in_progress: bool,
+    pub queued_update_requests: usize,
+    pub deployed_commit: Option<String>,
+    pub deployed_ref: Option<String>,
+    pub last_deploy: Option<DeploymentJob>,
+    pub candidate: Option<CandidateStatus>,
+    /// Comparison of the requests mirrored to the standby instance so far.
+    pub shadow: Option<ShadowSummary>,
+}
+
+/// A new build that is healthy but not live yet.
+#[derive(Clone, Debug, Serialize)]
+pub struct CandidateStatus {
+    pub job_id: u64,
+    pub instance: String,
+    /// Share of the requests it receives while canaried, unset while it awaits promotion.
+    pub canary_percent: Option<u8>,
+}
+
+/// Why a deploy stopped before the new instance went live.
+#[derive(Debug)]
+pub enum DeployError {
+    /// A deploy script failed to spawn or exited unsuccessfully.
+    Command {
+        phase: JobPhase,
+        source: CommandError,
+    },
+    /// The instance process could not be started.
+    InstanceStart { instance: String },
+    /// The instance started but never passed its health checks.
+    HealthCheck { instance: String },
+    /// Fetching or checking out the commit to deploy failed.
+    Git(GitError),
+    /// No stored release matched the rollback request.
+    ReleaseNotFound { commit: Option<String> },
+    /// The deploy was cancelled before traffic was switched over.
+    Cancelled,
+    /// A phase ran longer than its configured time limit.
+    Timeout { phase: JobPhase, limit: Duration },
+    /// The new build was discarded before it went live, traffic stayed on the old instance.
+    Discarded { instance: String },
+    /// Too many mirrored requests got a different answer from the new build.
+    ShadowMismatch { mismatched: u64, total: u64 },
+    /// The new instance failed too many requests after cutover and traffic went back to the old one.
+    ErrorRate {
+        instance: String,
+        errors: u64,
+        requests: u64,
+    },
+}
 
-        Self::start_instance("1").await;
+impl DeployError {
+    fn command(phase: JobPhase, source: CommandError) -> Self {
+        DeployError::Command { phase, source }
     }
+}
 
-    pub async fn on_update() {
-        if let Some(rx) = Self::queue_update_request() {
-            if rx.await.is_err() {
-                eprintln!("Update request was cancelled before execution");
-                return;
+impl fmt::Display for DeployError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DeployError::Command { phase, source } => {
+                write!(f, "{} failed: {}", phase.as_str(), source)
+            }
+            DeployError::Git(source) => {
+                write!(
+                    f,
+                    "{} failed: {}",
+                    JobPhase::PullingChanges.as_str(),
+                    source
+                )
             }
+            DeployError::InstanceStart { instance } => {
+                write!(f, "instance {instance} could not be started")
+            }
+            DeployError::HealthCheck { instance } => {
+                write!(f, "instance {instance} failed health checks after startup")
+            }
+            DeployError::ReleaseNotFound {
+                commit: Some(commit),
+            } => {
+                write!(f, "no stored release matches commit {commit}")
+            }
+            DeployError::ReleaseNotFound { commit: None } => {
+                write!(f, "no previous release available to roll back to")
+            }
+            DeployError::Cancelled => write!(f, "deploy was cancelled"),
+            DeployError::Timeout { phase, limit } => {
+                write!(f, "{} timed out after {}s", phase.as_str(), limit.as_secs())
+            }
+            DeployError::Discarded { instance } => {
+                write!(f, "new build on instance {instance} was discarded")
+            }
+            DeployError::ShadowMismatch { mismatched, total } => write!(
+                f,
+                "{mismatched} of {total} mirrored requests got a different answer from the new build"
+            ),
+            DeployError::ErrorRate {
+                instance,
+                errors,
+                requests,
+            } => write!(
+                f,
+                "instance {instance} failed {errors} of {requests} requests after cutover, switched back"
+            ),
+        }
+    }
+}
+
+impl std::error::Error for DeployError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            DeployError::Command { source, .. } => Some(source),
+            DeployError::Git(source) => Some(source),
+            _ => None,
         }
+    }
+}
 
-        Self::perform_update_sequence().await;
+/// Why a cancel request was refused.
+#[derive(Debug)]
+pub enum CancelError {
+    /// No update or rollback is running.
+    NothingRunning,
+    /// Traffic is already being switched to the new instance.
+    TooLate { job_id: u64 },
+}
 
-        Self::process_next_queued_update();
+impl fmt::Display for CancelError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CancelError::NothingRunning => write!(f, "no deploy is running"),
+            CancelError::TooLate { job_id } => write!(
+                f,
+                "job #{job_id} is already switching traffic and can no longer be cancelled"
+            ),
+        }
     }
+}
 
-    pub fn status_snapshot() -> InstanceStatus {
-        let state = STATE.read().unwrap();
-        InstanceStatus {
-            current_main_instance: if state.current_main_instance.is_empty() {
-                None
-            } else {
-                Some(state.current_main_instance.clone())
-            },
-            instance1_running: state.instance1_proc.is_some(),
-            instance2_running: state.instance2_proc.is_some(),
-            update_in_progress: state.update_in_progress,
-            queued_update_requests: state.queued_update_waiters.len(),
+/// Why a promote or discard request was refused.
+#[derive(Debug)]
+pub enum PromotionError {
+    /// No new build is awaiting promotion or being canaried.
+    NoCandidate,
+}
+
+impl fmt::Display for PromotionError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PromotionError::NoCandidate => {
+                write!(f, "no new build is awaiting promotion or being canaried")
+            }
         }
     }
+}
 
-    pub async fn shutdown() {
-        tracing::info!(target: "supervisor", "Shutting down runtime instances");
+/// What a queued job does once it reaches the front of the queue.
+enum DeployAction {
+    /// Deploys the pending update, with every request merged into it up to that point.
+    Update,
+    Rollback {
+        commit: Option<String>,
+    },
+}
 
-        Self::terminate_instance("1").await;
-        Self::terminate_instance("2").await;
+pub struct InstanceHandler {}
+
+impl InstanceHandler {
+    pub async fn startup() {
+        let job_id = Self::create_job(JobKind::Startup, Trigger::Startup);
 
+        // hold the update queue so webhooks arriving during the first build wait for it
+        if let Some(rx) = Self::queue_update_request()
+            && rx.await.is_err()
         {
-            let mut state = STATE.write().unwrap();
-            state.current_main_instance.clear();
-            state.update_in_progress = false;
-            state.queued_update_waiters.clear();
+            Self::finish_job(job_id, JobOutcome::Cancelled, None);
+            return;
         }
 
-        let cleanup_instances_result = Self::cleanup_instances().await;
-        if let Err(e) = cleanup_instances_result {
-            eprintln!("Error cleaning up instances during shutdown: {}", e);
-        }
-    }
+        Self::update_job(job_id, |job| job.started_at = Some(utils::unix_timestamp()));
+        deploy_logs::open(job_id);
 
-    fn queue_update_request() -> Option<oneshot::Receiver<()>> {
-        let mut state = STATE.write().unwrap();
-        if state.update_in_progress {
-            let (tx, rx) = oneshot::channel();
-            state.queued_update_waiters.push_back(tx);
-            Some(rx)
-        } else {
-            state.update_in_progress = true;
-            None
+        match Self::perform_startup_sequence(job_id).await {
+            Ok(()) => Self::finish_job(job_id, JobOutcome::Succeeded, None),
+            Err(e) => {
+                tracing::error!(target: "supervisor", error = %e, "startup deploy failed");
+                Self::finish_job(job_id, JobOutcome::Failed, Some(e.to_string()));
+            }
         }
+        deploy_logs::close(job_id);
+
+        Self::process_next_queued_update();
     }
 
-    fn process_next_queued_update() {
-        loop {
-            let next_waiter = {
-                let mut state = STATE.write().unwrap();
-                if let Some(waiter) = state.queued_update_waiters.pop_front() {
-                    Some(waiter)
-                } else {
-                    state.update_in_progress = false;
-                    None
-                }
-            };
+    async fn perform_startup_sequence(job_id: u64) -> Result<(), DeployError> {
+        // a ref pinned before the restart stays deployed instead of the tracked branch
+        let pinned_ref = history::deployed_ref();
+        Self::update_job(job_id, |job| job.git_ref = pinned_ref.clone());
 
-            match next_waiter {
-                Some(waiter) => {
-                    if waiter.send(()).is_ok() {
-                        break;
-                    }
-                }
-                None => break,
+        Self::set_job_phase(job_id, JobPhase::PullingChanges);
+        let sync = Self::with_timeout(JobPhase::PullingChanges, async {
+            git::sync(pinned_ref.as_deref())
+                .await
+                .map_err(DeployError::Git)
+        })
+        .await;
+        match sync {
+            Ok(sync) => Self::update_job(job_id, |job| {
+                job.previous_commit = sync.before;
+                job.force_pushed = sync.force_pushed;
+            }),
+            Err(e) => {
+                eprintln!("Error pulling latest git changes: {}", e);
+                // continue startup even if git pull fails
             }
         }
-    }
 
-    async fn perform_update_sequence() {
-        let old_main_instance: String;
+        let head_commit = git::head_commit().await;
+        let lockfile_hash = build_info::lockfile_hash().await;
+
+        // skip the build entirely if a slot already holds this exact commit
+        if let Some(instance) =
+            build_info::find_reusable(head_commit.as_deref(), lockfile_hash.as_deref())
         {
-            let state = STATE.read().unwrap();
-            old_main_instance = state.current_main_instance.clone();
-        }
-        let new_main_instance = if old_main_instance == "1" { "2" } else { "1" };
+            tracing::info!(
+                target: "supervisor",
+                instance,
+                commit = head_commit.as_deref().unwrap_or_default(),
+                "reusing existing build, repository has not changed"
+            );
 
-        let pull_latest_git_changes_proc = utils::run_cmd_with_logs(
-            "/usr/local/share/supervisor/scripts/pull_latest_git_changes.sh",
-            &[],
-            &[],
-        );
-        if let Err(e) = pull_latest_git_changes_proc.wait().await {
-            eprintln!("Error pulling latest git changes: {}", e);
-            return;
+            let other_instance = if instance == "1" { "2" } else { "1" };
+            if let Err(e) = Self::cleanup_instance(other_instance).await {
+                eprintln!("Error cleaning up instance {}: {}", other_instance, e);
+            }
+
+            match Self::start_startup_instance(job_id, instance, head_commit.clone()).await {
+                Ok(()) => return Ok(()),
+                Err(e) => {
+                    tracing::warn!(
+                        target: "supervisor",
+                        error = %e,
+                        "existing build did not come up, rebuilding"
+                    );
+                }
+            }
         }
 
-        let create_new_build_proc = utils::run_cmd_with_logs(
-            "/usr/local/share/supervisor/scripts/create_new_build.sh",
-            &[],
-            &[],
-        );
-        if let Err(e) = create_new_build_proc.wait().await {
-            eprintln!("Error creating new build: {}", e);
-            return;
+        if let Err(e) = Self::cleanup_instances().await {
+            eprintln!("Error cleaning up instances: {}", e);
+            // continue startup even if cleanup fails
         }
 
-        let move_build_to_instance_proc = utils::run_cmd_with_logs(
-            "/usr/local/share/supervisor/scripts/move_build_to_instance.sh",
-            &[new_main_instance],
+        Self::set_job_phase(job_id, JobPhase::InstallingDependencies);
+        Self::run_step_script(
+            JobPhase::InstallingDependencies,
+            "install_dependencies.sh",
             &[],
-        );
-        if let Err(e) = move_build_to_instance_proc.wait().await {
-            eprintln!(
-                "Error moving build to instance {}: {}",
-                new_main_instance, e
-            );
-            Self::cleanup_instance(new_main_instance).await.ok();
-        }
+        )
+        .await?;
 
-        let startup_success = Self::start_instance(new_main_instance).await;
-        if !startup_success {
-            eprintln!(
-                "Error starting instance {}: startup failed",
-                new_main_instance
-            );
-            Self::cleanup_instance(new_main_instance).await.ok();
-            return;
-        }
-        // wait and check health
-        let mut healthy = false;
-        for _ in 0..10 {
-            tokio::time::sleep(std::time::Duration::from_secs(3)).await;
-            healthy = Self::check_instance_health(new_main_instance).await;
-            if healthy {
-                break;
-            }
-        }
-        if !healthy {
-            eprintln!(
-                "Instance {} failed health checks after startup",
-                new_main_instance
-            );
-            Self::terminate_instance(new_main_instance).await;
-            Self::cleanup_instance(new_main_instance).await.ok();
-            return;
+        Self::set_job_phase(job_id, JobPhase::Building);
+        Self::run_step_script(JobPhase::Building, "create_new_build.sh", &[]).await?;
+
+        Self::set_job_phase(job_id, JobPhase::MovingBuild);
+        Self::run_step_script(JobPhase::MovingBuild, "move_build_to_instance.sh", &["1"]).await?;
+        BuildInfo::new(head_commit.clone(), lockfile_hash).write("1");
+
+        Self::start_startup_instance(job_id, "1", head_commit.clone()).await?;
+
+        if let Some(commit) = head_commit.as_deref() {
+            Self::archive_release("1", commit).await;
         }
 
+        Ok(())
+    }
+
+    /// Starts the first instance after boot and points the proxy at it once healthy.
+    async fn start_startup_instance(
+        job_id: u64,
+        instance_number: &str,
+        commit: Option<String>,
+    ) -> Result<(), DeployError> {
+        Self::update_job(job_id, |job| job.commit = commit.clone());
         {
             let mut state = STATE.write().unwrap();
-            state.current_main_instance = new_main_instance.to_string();
+            state.current_main_instance = instance_number.to_string();
+            state.deployed_commit = commit;
+            state.deployed_ref = state.jobs.get(&job_id).and_then(|job| job.git_ref.clone());
         }
 
-        // wait a bit to ensure the new instance is fully started
-        tokio::time::sleep(std::time::Duration::from_secs(10)).await;
+        Self::set_job_phase(job_id, JobPhase::StartingInstance);
+        Self::reset_slot_watch(instance_number);
+        if !Self::start_instance(instance_number) {
+            return Err(DeployError::InstanceStart {
+                instance: instance_number.to_string(),
+            });
+        }
 
-        //update reverse proxy to point to new instance
-        if let Err(err) = proxy::set_world_backend(if new_main_instance == "1" {
-            "127.0.0.1:19131"
-        } else {
-            "127.0.0.1:19132"
-        }) {
+        Self::set_job_phase(job_id, JobPhase::HealthChecking);
+        if let Err(e) = Self::wait_until_healthy(instance_number).await {
+            Self::terminate_instance(instance_number).await;
+            return Err(e);
+        }
+
+        // the proxy keeps serving the unavailable page until this point
+        Self::set_job_phase(job_id, JobPhase::SwitchingBackend);
+        Self::update_job(job_id, |job| {
+            job.live_instance = Some(instance_number.to_string())
+        });
+        let backend = config::get().instance_backend(instance_number);
+        if let Err(err) = proxy::set_world_backend(&backend) {
             eprintln!(
                 "Error updating reverse proxy to instance {}: {}",
-                new_main_instance, err
+                instance_number, err
             );
         }
 
-        // stop the old instance
-        Self::terminate_instance(old_main_instance.as_str()).await;
-
-        let cleanup_old_instance_result = Self::cleanup_instance(old_main_instance.as_str()).await;
-        if let Err(e) = cleanup_old_instance_result {
-            eprintln!("Error cleaning up instance {}: {}", old_main_instance, e);
-        }
+        Ok(())
     }
 
-    async fn start_instance(instance_number: &str) -> bool {
-        let mut state = STATE.write().unwrap();
+    /// Runs an update and waits for it to finish.
+    /// With a ref, that branch, tag or commit is deployed instead of the tracked branch.
+    /// `force` rebuilds even if the commit is already deployed.
+    pub async fn on_update(
+        git_ref: Option<String>,
+        force: bool,
+        trigger: Trigger,
+    ) -> Option<DeploymentJob> {
+        let job_id = Self::request_update(git_ref, None, force, trigger);
+        Self::wait_for_job(job_id).await
+    }
 
-        let instance_path = format!(
-            "/home/container/.app/instance/{}/server/index.mjs",
-            instance_number
-        );
-        let instance_args = [instance_path.as_str()];
+    /// Queues an update in the background and returns its job id right away.
+    /// If an update is already waiting, the request is merged into it and its job id is returned.
+    /// `expected_commit` is the commit a push announced, the tracked branch is still what gets deployed.
+    pub fn request_update(
+        git_ref: Option<String>,
+        expected_commit: Option<String>,
+        force: bool,
+        trigger: Trigger,
+    ) -> u64 {
+        let mut guard = STATE.write().unwrap();
+        let state = &mut *guard;
 
-        if instance_number == "1" {
-            // check if instance1_proc is already running, if so, error out
-            if state.instance1_proc.is_some() {
-                eprintln!("Instance 1 is already running.");
-                return false;
-            }
+        if let Some(pending) = state.pending_update.as_mut() {
+            // the newest request decides what gets deployed
+            pending.git_ref = git_ref;
+            pending.expected_commit = expected_commit;
+            pending.force |= force;
+            pending.last_requested_at = Instant::now();
 
-            state.instance1_proc = Some(utils::run_cmd_with_logs(
-                "bun",
-                &instance_args,
-                &[("NITRO_PORT", "19131"), ("NITRO_HOST", "127.0.0.1")],
-            ));
-        } else if instance_number == "2" {
-            // check if instance2_proc is already running, if so, error out
-            if state.instance2_proc.is_some() {
-                eprintln!("Instance 2 is already running.");
-                return false;
+            if let Some(job) = state.jobs.get_mut(&pending.job_id) {
+                job.git_ref = pending.git_ref.clone();
+                job.expected_commit = pending.expected_commit.clone();
+                job.trigger = trigger;
+                job.merged_requests += 1;
             }
+            tracing::info!(
+                target: "supervisor",
+                job_id = pending.job_id,
+                "merged update request into pending update"
+            );
+            return pending.job_id;
+        }
 
-            state.instance2_proc = Some(utils::run_cmd_with_logs(
-                "bun",
-                &instance_args,
-                &[("NITRO_PORT", "19132"), ("NITRO_HOST", "127.0.0.1")],
-            ));
+        let job_id = Self::insert_job(state, JobKind::Update, trigger);
+        if let Some(job) = state.jobs.get_mut(&job_id) {
+            job.git_ref = git_ref.clone();
+            job.expected_commit = expected_commit.clone();
         }
-        true
+        state.pending_update = Some(PendingUpdate {
+            job_id,
+            git_ref,
+            expected_commit,
+            force,
+            last_requested_at: Instant::now(),
+        });
+        drop(guard);
+
+        tokio::spawn(Self::run_update_job(job_id, DeployAction::Update));
+        job_id
     }
 
-    // async fn get_current_main_instance() -> String {
-    //     let state = STATE.read().unwrap();
-    //     state.current_main_instance.clone()
-    // }
+    /// Takes over the pending update of an update job and publishes the job as running.
+    /// The pending update is handed over first, so a cancel that wins before the deploy even starts
+    /// never leaves later requests merging into a finished job.
+    fn start_running_job(
+        job_id: u64,
+        action: &DeployAction,
+    ) -> (Option<PendingUpdate>, Arc<Notify>, &'static str) {
+        let pending = match action {
+            DeployAction::Update => Self::take_pending_update(job_id),
+            DeployAction::Rollback { .. } => None,
+        };
 
-    async fn terminate_instance(instance_number: &str) {
-        let proc = {
-            let mut state = STATE.write().unwrap();
-            if instance_number == "1" {
-                state.instance1_proc.take()
-            } else if instance_number == "2" {
-                state.instance2_proc.take()
-            } else {
-                None
-            }
+        let mut state = STATE.write().unwrap();
+        let cancel = Arc::new(Notify::new());
+        state.running_job = Some(RunningJob {
+            job_id,
+            cancel_requested: false,
+            cancel: cancel.clone(),
+        });
+        let standby = if state.current_main_instance == "1" {
+            "2"
+        } else {
+            "1"
         };
+        (pending, cancel, standby)
+    }
 
-        if let Some(mut proc) = proc {
-            let _ = proc.kill().await;
-        }
+    /// Hands the pending update over to its job, so new requests start a new pending update.
+    fn take_pending_update(job_id: u64) -> Option<PendingUpdate> {
+        let mut state = STATE.write().unwrap();
+        state
+            .pending_update
+            .take_if(|pending| pending.job_id == job_id)
     }
 
-    async fn cleanup_instance(instance_number: &str) -> Result<(), Error> {
-        let cleanup_instance_proc = utils::run_cmd_with_logs(
-            "/usr/local/share/supervisor/scripts/cleanup_instance.sh",
-            &[instance_number],
-            &[],
-        );
+    /// Waits until no update request has come in for the debounce window.
+    async fn debounce_pending_update() {
+        let Some(window) = config::get().debounce() else {
+            return;
+        };
 
-        cleanup_instance_proc.wait().await?;
+        loop {
+            let last_requested_at = {
+                let state = STATE.read().unwrap();
+                state
+                    .pending_update
+                    .as_ref()
+                    .map(|pending| pending.last_requested_at)
+            };
+            let Some(last_requested_at) = last_requested_at else {
+                return;
+            };
 
-        Ok(())
+            let elapsed = last_requested_at.elapsed();
+            if elapsed >= window {
+                return;
+            }
+            tokio::time::sleep(window - elapsed).await;
+        }
     }
 
-    async fn cleanup_instances() -> Result<(), Error> {
-        let cleanup_instances_proc = utils::run_cmd_with_logs(
-            "/usr/local/share/supervisor/scripts/cleanup_instances.sh",
-            &[],
-            &[],
-        );
+    /// Waits for a job to finish and returns it, `None` if the job is unknown.
+    async fn wait_for_job(job_id: u64) -> Option<DeploymentJob> {
+        let rx = {
+            let mut state = STATE.write().unwrap();
+            match state.jobs.get(&job_id) {
+                None => return None,
+                Some(job) if job.outcome.is_some() => return Some(job.clone()),
+                Some(_) => {}
+            }
 
-        cleanup_instances_proc.wait().await?;
+            let (tx, rx) = oneshot::channel();
+            state.job_waiters.entry(job_id).or_default().push(tx);
+            rx
+        };
 
+        let _ = rx.await;
+        Self::job(job_id)
+    }
+
+    /// Rolls back to a stored release and waits for it to finish.
+    /// Without a commit, the release archived before the deployed one is used.
+    pub async fn on_rollback(commit: Option<String>, trigger: Trigger) -> Option<DeploymentJob> {
+        let job_id = Self::create_job(JobKind::Rollback, trigger);
+        Self::run_update_job(job_id, DeployAction::Rollback { commit }).await;
+        Self::job(job_id)
+    }
+
+    /// Queues a rollback in the background and returns its job id right away.
+    pub fn enqueue_rollback(commit: Option<String>, trigger: Trigger) -> u64 {
+        let job_id = Self::create_job(JobKind::Rollback, trigger);
+        tokio::spawn(Self::run_update_job(
+            job_id,
+            DeployAction::Rollback { commit },
+        ));
+        job_id
+    }
+
+    pub fn releases() -> Vec<releases::Release> {
+        releases::list()
+    }
+
+    /// Recorded deployment jobs, newest first.
+    pub fn history(limit: usize) -> Vec<DeploymentJob> {
+        let mut records = history::list();
+        records.reverse();
+        records.truncate(limit);
+        records
+    }
+
+    /// Script and git output of a deployment, `None` once its log has been pruned.
+    pub fn deploy_log(job_id: u64) -> Option<String> {
+        deploy_logs::read(job_id)
+    }
+
+    /// A tracked job, or a finished one that has since been dropped from memory but is still in the history.
+    pub fn job(job_id: u64) -> Option<DeploymentJob> {
+        let tracked = STATE.read().unwrap().jobs.get(&job_id).cloned();
+        tracked.or_else(|| history::find(job_id))
+    }
+
+    async fn run_update_job(job_id: u64, action: DeployAction) {
+        if matches!(action, DeployAction::Update) {
+            Self::debounce_pending_update().await;
+        }
+
+        if let Some(rx) = Self::queue_update_request()
+            && rx.await.is_err()
+        {
+            eprintln!("Update request was cancelled before execution");
+            Self::take_pending_update(job_id);
+            Self::finish_job(job_id, JobOutcome::Cancelled, None);
+            return;
+        }
+
+        Self::update_job(job_id, |job| job.started_at = Some(utils::unix_timestamp()));
+        deploy_logs::open(job_id);
+
+        let (pending, cancel, standby_instance) = Self::start_running_job(job_id, &action);
+
+        let sequence = async {
+            match action {
+                DeployAction::Update => {
+                    let (git_ref, expected_commit, force) = match pending {
+                        Some(pending) => (pending.git_ref, pending.expected_commit, pending.force),
+                        None => (None, None, false),
+                    };
+                    Self::perform_update_sequence(job_id, git_ref, expected_commit, force).await
+                }
+                DeployAction::Rollback { commit } => {
+                    Self::perform_rollback_sequence(job_id, commit).await
+                }
+            }
+        };
+
+        // dropping the sequence stops it at its current step, see begin_switch for why that is safe
+        let result = tokio::select! {
+            result = sequence => result,
+            _ = cancel.notified() => Err(DeployError::Cancelled),
+        };
+
+        STATE.write().unwrap().running_job = None;
+
+        match result {
+            Ok(outcome) => Self::finish_job(job_id, outcome, None),
+            Err(DeployError::Cancelled) => {
+                tracing::warn!(
+                    target: "supervisor",
+                    job_id,
+                    instance = standby_instance,
+                    "deploy cancelled, cleaning up standby instance"
+                );
+                Self::finish_shadow(job_id);
+                Self::clear_candidate();
+                Self::terminate_instance(standby_instance).await;
+                if let Err(e) = Self::cleanup_instance(standby_instance).await {
+                    eprintln!("Error cleaning up instance {}: {}", standby_instance, e);
+                }
+                Self::finish_job(
+                    job_id,
+                    JobOutcome::Cancelled,
+                    Some(DeployError::Cancelled.to_string()),
+                );
+            }
+            Err(e @ DeployError::Discarded { .. }) => {
+                tracing::warn!(target: "supervisor", job_id, "{e}");
+                Self::finish_job(job_id, JobOutcome::Cancelled, Some(e.to_string()));
+            }
+            Err(e) => {
+                tracing::error!(target: "supervisor", job_id, error = %e, "deploy failed");
+                Self::finish_job(job_id, JobOutcome::Failed, Some(e.to_string()));
+            }
+        }
+        deploy_logs::close(job_id);
+
+        Self::process_next_queued_update();
+    }
+
+    /// Cancels the running update or rollback, killing its current script.
+    /// The main instance keeps serving and the standby slot is cleaned up.
+    pub async fn cancel_deploy() -> Result<u64, CancelError> {
+        let (job_id, script_pid, cancel) = {
+            let mut guard = STATE.write().unwrap();
+            let state = &mut *guard;
+            let Some(running) = state.running_job.as_mut() else {
+                return Err(CancelError::NothingRunning);
+            };
+
+            let phase = state.jobs.get(&running.job_id).map(|job| job.phase);
+            if matches!(
+                phase,
+                Some(
+                    JobPhase::SwitchingBackend
+                        | JobPhase::Monitoring
+                        | JobPhase::CleaningUp
+                        | JobPhase::Finished
+                )
+            ) {
+                return Err(CancelError::TooLate {
+                    job_id: running.job_id,
+                });
+            }
+
+            running.cancel_requested = true;
+            let job_id = running.job_id;
+            let cancel = running.cancel.clone();
+            (job_id, state.deploy_script_pid, cancel)
+        };
+
+        tracing::warn!(target: "supervisor", job_id, "cancelling deploy");
+        if let Some(pid) = script_pid {
+            utils::kill_process_group(pid);
+        }
+        cancel.notify_one();
+
+        Ok(job_id)
+    }
+
+    /// Moves a job into the switching phase unless it was cancelled first.
+    /// Past this point cancel requests are refused, so a job is never dropped halfway through a switch.
+    fn begin_switch(job_id: u64) -> Result<(), DeployError> {
+        let mut guard = STATE.write().unwrap();
+        let state = &mut *guard;
+        if state
+            .running_job
+            .as_ref()
+            .is_some_and(|running| running.job_id == job_id && running.cancel_requested)
+        {
+            return Err(DeployError::Cancelled);
+        }
+
+        if let Some(job) = state.jobs.get_mut(&job_id) {
+            job.enter_phase(JobPhase::SwitchingBackend);
+        }
+        drop(guard);
+
+        let message = format!("entering phase {}", JobPhase::SwitchingBackend.as_str());
+        deploy_logs::note(job_id, &message);
         Ok(())
     }
 
-    async fn check_instance_health(instance_number: &str) -> bool {
-        let port = if instance_number == "1" { 19131 } else { 19132 };
+    pub fn status_snapshot() -> InstanceStatus {
+        let state = STATE.read().unwrap();
+        InstanceStatus {
+            current_main_instance: if state.current_main_instance.is_empty() {
+                None
+            } else {
+                Some(state.current_main_instance.clone())
+            },
+            instance1_running: state.instance1_proc.is_some(),
+            instance2_running: state.instance2_proc.is_some(),
+            instance1_failed: state.slot_watches.get("1").is_some_and(|w| w.failed),
+            instance2_failed: state.slot_watches.get("2").is_some_and(|w| w.failed),
+            instance1_last_exit: state
+                .slot_watches
+                .get("1")
+                .and_then(|w| w.last_exit.clone()),
+            instance2_last_exit: state
+                .slot_watches
+                .get("2")
+                .and_then(|w| w.last_exit.clone()),
+            update_in_progress: state.update_in_progress,
+            queued_update_requests: state.queued_update_waiters.len(),
+            deployed_commit: state.deployed_commit.clone(),
+            deployed_ref: state.deployed_ref.clone(),
+            last_deploy: state.last_deploy.clone(),
+            candidate: state.candidate.as_ref().map(|candidate| CandidateStatus {
+                job_id: candidate.job_id,
+                instance: candidate.instance.clone(),
+                canary_percent: candidate.canary_percent,
+            }),
+            shadow: shadow::summary(),
+        }
+    }
+
+    pub async fn shutdown() {
+        tracing::info!(target: "supervisor", "Shutting down runtime instances");
+
+        Self::terminate_instance("1").await;
+        Self::terminate_instance("2").await;
+
+        let main_instance = {
+            let mut state = STATE.write().unwrap();
+            let main_instance = std::mem::take(&mut state.current_main_instance);
+            state.update_in_progress = false;
+            state.queued_update_waiters.clear();
+            main_instance
+        };
+
+        // keep the live build around so the next start can reuse it
+        let cleanup_result = match main_instance.as_str() {
+            "1" => Self::cleanup_instance("2").await,
+            "2" => Self::cleanup_instance("1").await,
+            _ => Self::cleanup_instances().await,
+        };
+        if let Err(e) = cleanup_result {
+            eprintln!("Error cleaning up instances during shutdown: {}", e);
+        }
+    }
+
+    fn create_job(kind: JobKind, trigger: Trigger) -> u64 {
+        let mut state = STATE.write().unwrap();
+        Self::insert_job(&mut state, kind, trigger)
+    }
+
+    fn insert_job(state: &mut AppState, kind: JobKind, trigger: Trigger) -> u64 {
+        let job_id = state.next_job_id;
+        state.next_job_id += 1;
+
+        state.jobs.insert(
+            job_id,
+            DeploymentJob {
+                id: job_id,
+                kind,
+                trigger,
+                git_ref: None,
+                expected_commit: None,
+                previous_commit: None,
+                commit: None,
+                force_pushed: false,
+                merged_requests: 0,
+                phase: JobPhase::Queued,
+                queued_at: utils::unix_timestamp(),
+                started_at: None,
+                finished_at: None,
+                outcome: None,
+                error: None,
+                phases: Vec::new(),
+                live_instance: None,
+                shadow: None,
+            },
+        );
+        state.job_order.push_back(job_id);
+
+        // forget the oldest finished jobs once the limit is reached
+        while state.job_order.len() > MAX_TRACKED_JOBS {
+            let Some(oldest) = state.job_order.front().copied() else {
+                break;
+            };
+            let finished = state
+                .jobs
+                .get(&oldest)
+                .is_none_or(|job| job.outcome.is_some());
+            if !finished {
+                break;
+            }
+            state.job_order.pop_front();
+            state.jobs.remove(&oldest);
+        }
+
+        job_id
+    }
+
+    fn update_job(job_id: u64, f: impl FnOnce(&mut DeploymentJob)) {
+        let mut state = STATE.write().unwrap();
+        if let Some(job) = state.jobs.get_mut(&job_id) {
+            f(job);
+        }
+    }
+
+    fn set_job_phase(job_id: u64, phase: JobPhase) {
+        Self::update_job(job_id, |job| job.enter_phase(phase));
+        deploy_logs::note(job_id, &format!("entering phase {}", phase.as_str()));
+    }
+
+    fn finish_job(job_id: u64, outcome: JobOutcome, error: Option<String>) {
+        let finished_job = {
+            let mut state = STATE.write().unwrap();
+            let Some(job) = state.jobs.get_mut(&job_id) else {
+                return;
+            };
+
+            job.enter_phase(JobPhase::Finished);
+            job.finished_at = Some(utils::unix_timestamp());
+            job.outcome = Some(outcome);
+            job.error = error;
+
+            let finished_job = job.clone();
+            if outcome.is_deploy() {
+                state.last_deploy = Some(finished_job.clone());
+            }
+
+            for waiter in state.job_waiters.remove(&job_id).unwrap_or_default() {
+                let _ = waiter.send(());
+            }
+            finished_job
+        };
+
+        let message = match &finished_job.error {
+            Some(error) => format!("job finished: {} ({error})", outcome.as_str()),
+            None => format!("job finished: {}", outcome.as_str()),
+        };
+        deploy_logs::note(job_id, &message);
+        history::append(&finished_job);
+    }
+
+    fn queue_update_request() -> Option<oneshot::Receiver<()>> {
+        let mut state = STATE.write().unwrap();
+        if state.update_in_progress {
+            let (tx, rx) = oneshot::channel();
+            state.queued_update_waiters.push_back(tx);
+            Some(rx)
+        } else {
+            state.update_in_progress = true;
+            None
+        }
+    }
+
+    fn process_next_queued_update() {
+        loop {
+            let next_waiter = {
+                let mut state = STATE.write().unwrap();
+                if let Some(waiter) = state.queued_update_waiters.pop_front() {
+                    Some(waiter)
+                } else {
+                    state.update_in_progress = false;
+                    None
+                }
+            };
+
+            match next_waiter {
+                Some(waiter) => {
+                    if waiter.send(()).is_ok() {
+                        break;
+                    }
+                }
+                None => break,
+            }
+        }
+    }
 
-        let url = format!("http://127.0.0.1:{}", port);
-        let response = match reqwest::get(&url).await {
-            Ok(resp) => resp,
-            Err(_) => return false,
+    async fn perform_update_sequence(
+        job_id: u64,
+        git_ref: Option<String>,
+        expected_commit: Option<String>,
+        force: bool,
+    ) -> Result<JobOutcome, DeployError> {
+        let (old_main_instance, deployed_commit) = {
+            let state = STATE.read().unwrap();
+            (
+                state.current_main_instance.clone(),
+                state.deployed_commit.clone(),
+            )
         };
+        let new_main_instance = if old_main_instance == "1" { "2" } else { "1" };
+
+        // a push of the commit that is already live needs no fetch
+        if !force && expected_commit.is_some() && deployed_commit == expected_commit {
+            tracing::info!(
+                target: "supervisor",
+                job_id,
+                commit = expected_commit.as_deref().unwrap_or_default(),
+                "pushed commit is already deployed, skipping rebuild"
+            );
+            return Ok(JobOutcome::Skipped);
+        }
+
+        Self::set_job_phase(job_id, JobPhase::PullingChanges);
+        let sync = Self::with_timeout(JobPhase::PullingChanges, async {
+            git::sync(git_ref.as_deref())
+                .await
+                .map_err(DeployError::Git)
+        })
+        .await?;
+        Self::update_job(job_id, |job| {
+            job.previous_commit = sync.before.clone();
+            job.commit = Some(sync.after.clone());
+            job.force_pushed = sync.force_pushed;
+        });
+
+        if let Some(expected) = expected_commit.as_deref()
+            && expected != sync.after
+        {
+            tracing::info!(
+                target: "supervisor",
+                job_id,
+                pushed = expected,
+                head = %sync.after,
+                "branch moved on since the push, deploying its current head"
+            );
+        }
+
+        if sync.force_pushed {
+            tracing::warn!(
+                target: "supervisor",
+                job_id,
+                before = sync.before.as_deref().unwrap_or_default(),
+                after = %sync.after,
+                "history was rewritten, the new commit does not descend from the old one"
+            );
+        }
+
+        if !force && deployed_commit.as_deref() == Some(sync.after.as_str()) {
+            tracing::info!(
+                target: "supervisor",
+                job_id,
+                commit = %sync.after,
+                "commit is already deployed, skipping rebuild"
+            );
+            return Ok(JobOutcome::Skipped);
+        }
+
+        let new_commit = Some(sync.after);
+        let lockfile_hash = build_info::lockfile_hash().await;
+
+        Self::set_job_phase(job_id, JobPhase::InstallingDependencies);
+        Self::run_step_script(
+            JobPhase::InstallingDependencies,
+            "install_dependencies.sh",
+            &[],
+        )
+        .await?;
+
+        Self::set_job_phase(job_id, JobPhase::Building);
+        Self::run_step_script(JobPhase::Building, "create_new_build.sh", &[]).await?;
 
-        let status = response.status().as_u16();
-        let healthy_codes = [200..=299, 300..=399, 400..=405];
-        healthy_codes.iter().any(|range| range.contains(&status))
+        Self::set_job_phase(job_id, JobPhase::MovingBuild);
+        if let Err(e) = Self::run_step_script(
+            JobPhase::MovingBuild,
+            "move_build_to_instance.sh",
+            &[new_main_instance],
+        )
+        .await
+        {
+            Self::cleanup_instance(new_main_instance).await.ok();
+            return Err(e);
+        }
+        BuildInfo::new(new_commit.clone(), lockfile_hash).write(new_main_instance);
+
+        Self::activate_instance(
+            job_id,
+            &old_main_instance,
+            new_main_instance,
+            new_commit.clone(),
+            true,
+        )
+        .await?;
+
+        if let Some(commit) = new_commit.as_deref() {
+            Self::archive_release(new_main_instance, commit).await;
+        }
+
+        Ok(JobOutcome::Succeeded)
     }
 
+    async fn perform_rollback_sequence(
+        job_id: u64,
+        commit: Option<String>,
+    ) -> Result<JobOutcome, DeployError> {
+        let (old_main_instance, deployed_commit) = {
+            let state = STATE.read().unwrap();
+            (
+                state.current_main_instance.clone(),
+                state.deployed_commit.clone(),
+            )
+        };
+        let new_main_instance = if old_main_instance == "1" { "2" } else { "1" };
+
+        let release = releases::find(commit.as_deref(), deployed_commit.as_deref())
+            .ok_or(DeployError::ReleaseNotFound { commit })?;
+        // the release is pinned, so neither the poller nor a restart moves back to the branch head
+        Self::update_job(job_id, |job| {
+            job.git_ref = Some(release.commit.clone());
+            job.previous_commit = deployed_commit;
+            job.commit = Some(release.commit.clone());
+        });
+
+        tracing::info!(
+            target: "supervisor",
+            job_id,
+            commit = %release.commit,
+            "rolling back to stored release"
+        );
+
+        Self::set_job_phase(job_id, JobPhase::MovingBuild);
+        if let Err(e) = Self::run_step_script(
+            JobPhase::MovingBuild,
+            "move_release_to_instance.sh",
+            &[release.commit.as_str(), new_main_instance],
+        )
+        .await
+        {
+            Self::cleanup_instance(new_main_instance).await.ok();
+            return Err(e);
+        }
+        // the lockfile of a past release is unknown, so it is never reused on restart
+        BuildInfo::new(Some(release.commit.clone()), None).write(new_main_instance);
+
+        Self::activate_instance(
+            job_id,
+            &old_main_instance,
+            new_main_instance,
+            Some(release.commit),
+            false,
+        )
+        .await?;
+
+        Ok(JobOutcome::Succeeded)
+    }
+
+    /// Starts a prepared instance, health checks it and switches traffic over to it.
+    /// A `new_build` is canaried and watched after cutover when configured, rollbacks
+    /// go back to a known release and switch at once.
+    async fn activate_instance(
+        job_id: u64,
+        old_main_instance: &str,
+        new_main_instance: &str,
+        new_commit: Option<String>,
+        new_build: bool,
+    ) -> Result<(), DeployError> {
+        Self::set_job_phase(job_id, JobPhase::StartingInstance);
+        Self::reset_slot_watch(new_main_instance);
+        let startup_success = Self::start_instance(new_main_instance);
+        if !startup_success {
+            Self::cleanup_instance(new_main_instance).await.ok();
+            return Err(DeployError::InstanceStart {
+                instance: new_main_instance.to_string(),
+            });
+        }
+        // wait and check health
+        Self::set_job_phase(job_id, JobPhase::HealthChecking);
+        if let Err(e) = Self::wait_until_healthy(new_main_instance).await {
+            Self::terminate_instance(new_main_instance).await;
+            Self::cleanup_instance(new_main_instance).await.ok();
+            return Err(e);
+        }
+        let config = config::get();
+
+        // let QA reach the new build with a preview token before it goes live
+        if let Err(err) = proxy::set_preview_backend(&config.instance_backend(new_main_instance)) {
+            eprintln!(
+                "Error routing preview traffic to instance {}: {}",
+                new_main_instance, err
+            );
+        }
+
+        let old_running = Self::is_instance_running(old_main_instance);
+        // mirroring needs the old instance to answer the live requests
+        let shadow = new_build && old_running && config.shadow_enabled();
+        if shadow {
+            shadow::start(&config.instance_backend(new_main_instance));
+        }
+
+        if new_build && config.manual_promotion() {
+            Self::await_promotion(job_id, new_main_instance).await?;
+            Self::finish_shadow(job_id);
+        } else if shadow {
+            Self::run_shadow_window(job_id, new_main_instance).await?;
+        }
+
+        let canary = new_build && old_running && config.canary.enabled;
+        if canary {
+            Self::run_canary(job_id, new_main_instance).await?;
+        }
+
+        Self::begin_switch(job_id)?;
+        let (previous_commit, previous_ref) = {
+            let mut state = STATE.write().unwrap();
+            state.current_main_instance = new_main_instance.to_string();
+            let previous_commit = std::mem::replace(&mut state.deployed_commit, new_commit);
+            let job = state.jobs.get_mut(&job_id);
+            let git_ref = job.and_then(|job| {
+                job.live_instance = Some(new_main_instance.to_string());
+                job.git_ref.clone()
+            });
+            let previous_ref = std::mem::replace(&mut state.deployed_ref, git_ref);
+            (previous_commit, previous_ref)
+        };
+
+        // wait a bit to ensure the new instance is fully started, a canary has already served traffic
+        if !canary {
+            tokio::time::sleep(std::time::Duration::from_secs(10)).await;
+        }
+
+        //update reverse proxy to point to new instance
+        let new_backend = config.instance_backend(new_main_instance);
+        proxy::reset_backend_stats(&new_backend);
+        if let Err(err) = proxy::set_world_backend(&new_backend) {
+            eprintln!(
+                "Error updating reverse proxy to instance {}: {}",
+                new_main_instance, err
+            );
+        }
+        proxy::clear_canary();
+        proxy::remove_preview_backend(&new_backend);
+
+        // keep the old instance as a fallback until the new build has proven itself
+        if new_build
+            && old_running
+            && let Some(window) = config.auto_rollback_window()
+        {
+            Self::set_job_phase(job_id, JobPhase::Monitoring);
+            if let Err(e) = Self::watch_error_rate(new_main_instance, window).await {
+                if Self::is_instance_running(old_main_instance) {
+                    tracing::error!(target: "supervisor", job_id, error = %e, "switching back to the old instance");
+                    Self::switch_back(
+                        job_id,
+                        old_main_instance,
+                        new_main_instance,
+                        previous_commit,
+                        previous_ref,
+                    )
+                    .await;
+                    return Err(e);
+                }
+                tracing::error!(
+                    target: "supervisor",
+                    job_id,
+                    error = %e,
+                    "old instance is no longer running, keeping the new build"
+                );
+            }
+        }
+
+        // stop the old instance
+        Self::set_job_phase(job_id, JobPhase::CleaningUp);
+        Self::terminate_instance(old_main_instance).await;
+
+        let cleanup_old_instance_result = Self::cleanup_instance(old_main_instance).await;
+        if let Err(e) = cleanup_old_instance_result {
+            eprintln!("Error cleaning up instance {}: {}", old_main_instance, e);
+        }
+
+        Ok(())
+    }
+
+    /// Watches the new instance's share of failed requests until `window` has passed.
+    async fn watch_error_rate(instance_number: &str, window: Duration) -> Result<(), DeployError> {
+        let config = config::get();
+        let backend = config.instance_backend(instance_number);
+        let max_error_percent = f64::from(config.auto_rollback.max_error_percent);
+        let deadline = tokio::time::Instant::now() + window;
+
+        loop {
+            let now = tokio::time::Instant::now();
+            if now >= deadline {
+                return Ok(());
+            }
+            tokio::time::sleep(ERROR_RATE_CHECK_INTERVAL.min(deadline - now)).await;
+
+            let stats = proxy::backend_stats(&backend);
+            if stats.requests >= config.auto_rollback.min_requests
+                && stats.error_percent() > max_error_percent
+            {
+                return Err(DeployError::ErrorRate {
+                    instance: instance_number.to_string(),
+                    errors: stats.errors(),
+                    requests: stats.requests,
+                });
+            }
+        }
+    }
+
+    /// Sends traffic back to the old instance after a failed cutover and stops the new one.
+    async fn switch_back(
+        job_id: u64,
+        old_main_instance: &str,
+        new_main_instance: &str,
+        previous_commit: Option<String>,
+        previous_ref: Option<String>,
+    ) {
+        let config = config::get();
+        if let Err(err) = proxy::set_world_backend(&config.instance_backend(old_main_instance)) {
+            eprintln!(
+                "Error updating reverse proxy to instance {}: {}",
+                old_main_instance, err
+            );
+        }
+        {
+            let mut state = STATE.write().unwrap();
+            state.current_main_instance = old_main_instance.to_string();
+            state.deployed_commit = previous_commit;
+            state.deployed_ref = previous_ref;
+        }
+        deploy_logs::note(
+            job_id,
+            &format!("switched back to instance {old_main_instance}"),
+        );
+
+        Self::set_job_phase(job_id, JobPhase::CleaningUp);
+        Self::terminate_instance(new_main_instance).await;
+        if let Err(e) = Self::cleanup_instance(new_main_instance).await {
+            eprintln!("Error cleaning up instance {}: {}", new_main_instance, e);
+        }
+    }
+
+    /// Mirrors live requests to the new instance for the configured window and fails the
+    /// deploy if too many of them were answered differently.
+    async fn run_shadow_window(job_id: u64, new_main_instance: &str) -> Result<(), DeployError> {
+        let config = config::get();
+        Self::set_job_phase(job_id, JobPhase::Shadowing);
+        tokio::time::sleep(config.shadow_window()).await;
+
+        let Some(summary) = Self::finish_shadow(job_id) else {
+            return Ok(());
+        };
+        let total = summary.samples + summary.failures;
+        if total < config.shadow.min_samples
+            || summary.mismatch_percent() <= f64::from(config.shadow.max_mismatch_percent)
+        {
+            return Ok(());
+        }
+
+        Self::terminate_instance(new_main_instance).await;
+        if let Err(e) = Self::cleanup_instance(new_main_instance).await {
+            eprintln!("Error cleaning up instance {}: {}", new_main_instance, e);
+        }
+        Err(DeployError::ShadowMismatch {
+            mismatched: summary.status_mismatches + summary.failures,
+            total,
+        })
+    }
+
+    /// Stops mirroring and records the comparison in the job.
+    fn finish_shadow(job_id: u64) -> Option<ShadowSummary> {
+        let summary = shadow::stop()?;
+        deploy_logs::note(
+            job_id,
+            &format!(
+                "shadow: {} mirrored, {} status mismatches, {} failed, avg latency {}ms main / {}ms new",
+                summary.samples + summary.failures,
+                summary.status_mismatches,
+                summary.failures,
+                summary.avg_main_latency_ms(),
+                summary.avg_shadow_latency_ms()
+            ),
+        );
+        Self::update_job(job_id, |job| job.shadow = Some(summary.clone()));
+        Some(summary)
+    }
+
+    /// Leaves the healthy new instance in the standby slot until it is promoted or discarded.
+    async fn await_promotion(job_id: u64, new_main_instance: &str) -> Result<(), DeployError> {
+        Self::set_job_phase(job_id, JobPhase::AwaitingPromotion);
+        let signal = Self::register_candidate(job_id, new_main_instance, None);
+        tracing::info!(
+            target: "supervisor",
+            job_id,
+            instance = new_main_instance,
+            "new build is healthy and awaits promotion"
+        );
+        deploy_logs::note(job_id, "awaiting promotion");
+
+        let decision = loop {
+            signal.notified().await;
+            if let Some(decision) = Self::candidate_decision() {
+                break decision;
+            }
+        };
+
+        match decision {
+            CandidateDecision::Promote => {
+                // the candidate may have crashed while it waited
+                Self::verify_candidate(job_id, new_main_instance).await?;
+                STATE.write().unwrap().candidate = None;
+                deploy_logs::note(job_id, "promoted");
+                Ok(())
+            }
+            CandidateDecision::Discard => Self::discard_candidate(job_id, new_main_instance).await,
+        }
+    }
+
+    /// Sends a growing share of the traffic to the new instance until it gets all of it,
+    /// or until it is promoted or discarded.
+    async fn run_canary(job_id: u64, new_main_instance: &str) -> Result<(), DeployError> {
+        let config = config::get();
+        let mut percent = config.canary.initial_percent;
+        if let Err(err) =
+            proxy::set_canary_backend(&config.instance_backend(new_main_instance), percent)
+        {
+            eprintln!(
+                "Error routing canary traffic to instance {}, switching at once: {}",
+                new_main_instance, err
+            );
+            return Ok(());
+        }
+
+        Self::set_job_phase(job_id, JobPhase::Canary);
+        let signal = Self::register_candidate(job_id, new_main_instance, Some(percent));
+        deploy_logs::note(job_id, &format!("canary receives {percent}% of requests"));
+
+        let decision = loop {
+            if percent >= 100 {
+                break CandidateDecision::Promote;
+            }
+
+            tokio::select! {
+                _ = tokio::time::sleep(config.canary_step_interval()) => {}
+                _ = signal.notified() => {}
+            }
+
+            if Self::candidate_decision().is_none() {
+                Self::verify_candidate(job_id, new_main_instance).await?;
+            }
+
+            let mut state = STATE.write().unwrap();
+            let Some(candidate) = state.candidate.as_mut() else {
+                break CandidateDecision::Discard;
+            };
+            if let Some(decision) = candidate.decision {
+                break decision;
+            }
+            percent = percent.saturating_add(config.canary.step_percent).min(100);
+            candidate.canary_percent = Some(percent);
+            drop(state);
+
+            proxy::set_canary_percent(percent);
+            deploy_logs::note(job_id, &format!("canary receives {percent}% of requests"));
+        };
+
+        match decision {
+            CandidateDecision::Promote => {
+                Self::verify_candidate(job_id, new_main_instance).await?;
+                STATE.write().unwrap().candidate = None;
+                deploy_logs::note(job_id, "canary promoted");
+                Ok(())
+            }
+            CandidateDecision::Discard => Self::discard_candidate(job_id, new_main_instance).await,
+        }
+    }
+
+    /// Switches to the new build waiting in the standby slot, skipping the rest of a canary.
+    pub fn promote() -> Result<u64, PromotionError> {
+        Self::decide_candidate(CandidateDecision::Promote)
+    }
+
+    /// Drops the new build waiting in the standby slot, traffic stays on the main instance.
+    pub fn discard() -> Result<u64, PromotionError> {
+        Self::decide_candidate(CandidateDecision::Discard)
+    }
+
+    fn decide_candidate(decision: CandidateDecision) -> Result<u64, PromotionError> {
+        let mut state = STATE.write().unwrap();
+        let candidate = state
+            .candidate
+            .as_mut()
+            .filter(|candidate| candidate.decision.is_none())
+            .ok_or(PromotionError::NoCandidate)?;
+        candidate.decision = Some(decision);
+        candidate.signal.notify_one();
+        Ok(candidate.job_id)
+    }
+
+    fn register_candidate(job_id: u64, instance: &str, canary_percent: Option<u8>) -> Arc<Notify> {
+        let signal = Arc::new(Notify::new());
+        STATE.write().unwrap().candidate = Some(Candidate {
+            job_id,
+            instance: instance.to_string(),
+            canary_percent,
+            decision: None,
+            signal: signal.clone(),
+        });
+        signal
+    }
+
+    fn candidate_decision() -> Option<CandidateDecision> {
+        let state = STATE.read().unwrap();
+        state
+            .candidate
+            .as_ref()
+            .and_then(|candidate| candidate.decision)
+    }
+
+    /// Takes a discarded candidate out of the proxy and stops it.
+    async fn discard_candidate(job_id: u64, instance_number: &str) -> Result<(), DeployError> {
+        Self::drop_candidate(job_id, instance_number, "discarded").await;
+        Err(DeployError::Discarded {
+            instance: instance_number.to_string(),
+        })
+    }
+
+    /// Makes sure a candidate is still running and healthy before it gets more traffic.
+    /// The standby slot is not restarted when it crashes, so a failed candidate is dropped
+    /// and the main instance stays live.
+    async fn verify_candidate(job_id: u64, instance_number: &str) -> Result<(), DeployError> {
+        let config = config::get();
+        let check = if Self::is_instance_running(instance_number) {
+            health::check(
+                &config.instance_backend(instance_number),
+                &config.health_check(),
+            )
+            .await
+        } else {
+            Err("instance is not running".to_string())
+        };
+
+        let Err(reason) = check else {
+            return Ok(());
+        };
+        tracing::warn!(
+            target: "supervisor",
+            job_id,
+            instance = instance_number,
+            "new build failed while waiting to go live: {reason}"
+        );
+        Self::drop_candidate(
+            job_id,
+            instance_number,
+            &format!("candidate failed: {reason}"),
+        )
+        .await;
+        Err(DeployError::HealthCheck {
+            instance: instance_number.to_string(),
+        })
+    }
+
+    /// Takes a candidate that will not go live out of the proxy and stops it.
+    async fn drop_candidate(job_id: u64, instance_number: &str, note: &str) {
+        Self::finish_shadow(job_id);
+        Self::clear_candidate();
+        deploy_logs::note(job_id, note);
+        Self::terminate_instance(instance_number).await;
+        if let Err(e) = Self::cleanup_instance(instance_number).await {
+            eprintln!("Error cleaning up instance {}: {}", instance_number, e);
+        }
+    }
+
+    /// Forgets a candidate that did not go live and stops routing canary traffic to it.
+    fn clear_candidate() {
+        STATE.write().unwrap().candidate = None;
+        proxy::clear_canary();
+    }
+
+    fn is_instance_running(instance_number: &str) -> bool {
+        let state = STATE.read().unwrap();
+        match instance_number {
+            "1" => state.instance1_proc.is_some(),
+            "2" => state.instance2_proc.is_some(),
+            _ => false,
+        }
+    }
+
+    fn start_instance(instance_number: &str) -> bool {
+        let config = config::get();
+        let mut state = STATE.write().unwrap();
+
+        let Some(proc_slot) = Self::instance_proc_mut(&mut state, instance_number) else {
+            eprintln!("Unknown instance {}.", instance_number);
+            return false;
+        };
+
+        // check if the instance is already running, if so, error out
+        if proc_slot.is_some() {
+            eprintln!("Instance {} is already running.", instance_number);
+            return false;
+        }
+
+        let instance_path = config
+            .instance_dir(instance_number)
+            .join(&config.instances.entry_point);
+        let instance_path = instance_path.to_string_lossy();
+        let instance_args = [instance_path.as_ref()];
+        let port = config.instance_port(instance_number).to_string();
+
+        match utils::run_cmd_with_logs(
+            &config.instances.runtime,
+            &instance_args,
+            &[
+                ("NITRO_PORT", port.as_str()),
+                ("NITRO_HOST", config.instances.host.as_str()),
+            ],
+        ) {
+            Ok(proc) => {
+                tokio::spawn(Self::watch_instance(instance_number.to_string(), proc.id()));
+                *proc_slot = Some(proc);
+                true
+            }
+            Err(e) => {
+                eprintln!("Error starting instance {}: {}", instance_number, e);
+                false
+            }
+        }
+    }
+
+    /// Watches an instance process and handles it exiting without being asked to.
+    /// Stops once the slot no longer holds the process it was started for.
+    async fn watch_instance(instance_number: String, pid: Option<u32>) {
+        loop {
+            tokio::time::sleep(Duration::from_secs(1)).await;
+
+            let exit_status = {
+                let mut state = STATE.write().unwrap();
+                let Some(proc_slot) = Self::instance_proc_mut(&mut state, &instance_number) else {
+                    return;
+                };
+                let exited = match proc_slot.as_mut() {
+                    Some(proc) if proc.id() == pid => proc.try_wait().ok().flatten(),
+                    // terminated or replaced by someone else
+                    _ => return,
+                };
+                if exited.is_some() {
+                    proc_slot.take();
+                }
+                exited
+            };
+
+            if let Some(status) = exit_status {
+                Self::on_instance_exit(&instance_number, status).await;
+                return;
+            }
+        }
+    }
+
+    async fn on_instance_exit(instance_number: &str, status: std::process::ExitStatus) {
+        let exit = InstanceExit {
+            code: status.code(),
+            signal: status.signal(),
+            exited_at: utils::unix_timestamp(),
+        };
+        tracing::error!(
+            target: "supervisor",
+            instance = instance_number,
+            code = ?exit.code,
+            signal = ?exit.signal,
+            "instance exited unexpectedly"
+        );
+
+        let restart_config = &config::get().restart;
+        let max_restarts = restart_config.max_restarts;
+        let restart_window = Duration::from_secs(restart_config.window_secs);
+        let backoff_base = Duration::from_secs(restart_config.backoff_secs);
+
+        let delay = {
+            let mut state = STATE.write().unwrap();
+            let is_main = state.current_main_instance == instance_number;
+            let watch = state
+                .slot_watches
+                .entry(instance_number.to_string())
+                .or_default();
+            watch.last_exit = Some(exit);
+
+            // only the instance serving traffic gets restarted
+            if !is_main {
+                return;
+            }
+
+            let now = Instant::now();
+            while watch
+                .recent_crashes
+                .front()
+                .is_some_and(|crash| now.duration_since(*crash) > restart_window)
+            {
+                watch.recent_crashes.pop_front();
+            }
+
+            if watch.recent_crashes.len() >= max_restarts {
+                watch.failed = true;
+                tracing::error!(
+                    target: "supervisor",
+                    instance = instance_number,
+                    "instance crashed {} times within {}s, giving up on restarts",
+                    watch.recent_crashes.len() + 1,
+                    restart_window.as_secs()
+                );
+                return;
+            }
+
+            let attempt = watch.recent_crashes.len() as u32;
+            watch.recent_crashes.push_back(now);
+            backoff_base
+                .checked_mul(2u32.saturating_pow(attempt))
+                .map_or(MAX_RESTART_BACKOFF, |backoff| {
+                    backoff.min(MAX_RESTART_BACKOFF)
+                })
+        };
+
+        tracing::info!(
+            target: "supervisor",
+            instance = instance_number,
+            "restarting instance in {}s",
+            delay.as_secs()
+        );
+        tokio::time::sleep(delay).await;
+
+        // a deploy may have moved traffic elsewhere while we were waiting
+        let should_restart = {
+            let mut state = STATE.write().unwrap();
+            state.current_main_instance == instance_number
+                && Self::instance_proc_mut(&mut state, instance_number)
+                    .is_some_and(|proc| proc.is_none())
+        };
+        if !should_restart {
+            return;
+        }
+
+        if !Self::start_instance(instance_number) {
+            let mut state = STATE.write().unwrap();
+            let watch = state
+                .slot_watches
+                .entry(instance_number.to_string())
+                .or_default();
+            watch.failed = true;
+        }
+    }
+
+    /// Clears crash history for a slot that is about to get a fresh build.
+    fn reset_slot_watch(instance_number: &str) {
+        let mut state = STATE.write().unwrap();
+        state.slot_watches.remove(instance_number);
+    }
+
+    fn instance_proc_mut<'a>(
+        state: &'a mut AppState,
+        instance_number: &str,
+    ) -> Option<&'a mut Option<utils::CommandHandle>> {
+        match instance_number {
+            "1" => Some(&mut state.instance1_proc),
+            "2" => Some(&mut state.instance2_proc),
+            _ => None,
+        }
+    }
+
+    // async fn get_current_main_instance() -> String {
+    //     let state = STATE.read().unwrap();
+    //     state.current_main_instance.clone()
+    // }
+
+    async fn terminate_instance(instance_number: &str) {
+        let proc = {
+            let mut state = STATE.write().unwrap();
+            if instance_number == "1" {
+                state.instance1_proc.take()
+            } else if instance_number == "2" {
+                state.instance2_proc.take()
+            } else {
+                None
+            }
+        };
+
+        let Some(mut proc) = proc else {
+            return;
+        };
+
+        // let in-flight requests on this instance finish before stopping it
+        let backend = config::get().instance_backend(instance_number);
+        proxy::remove_preview_backend(&backend);
+        let drain_timeout = config::get().drain_timeout();
+        let drain_deadline = tokio::time::Instant::now() + drain_timeout;
+        loop {
+            let active = proxy::active_requests(&backend);
+            if active == 0 {
+                break;
+            }
+            if tokio::time::Instant::now() >= drain_deadline {
+                tracing::warn!(
+                    target: "supervisor",
+                    instance = instance_number,
+                    active,
+                    "drain timeout reached, stopping instance with requests still in flight"
+                );
+                break;
+            }
+            tokio::time::sleep(std::time::Duration::from_millis(250)).await;
+        }
+
+        let term_grace = config::get().term_grace();
+        if let Err(e) = proc.terminate(term_grace).await {
+            eprintln!("Error stopping instance {}: {}", instance_number, e);
+        }
+    }
+
+    /// Runs one of the supervisor scripts from the configured scripts directory.
+    async fn run_script(name: &str, args: &[&str]) -> Result<(), CommandError> {
+        let config = config::get();
+        let script = config.script(name);
+        let env = config.script_env();
+        let env: Vec<(&str, &str)> = env.iter().map(|(k, v)| (*k, v.as_str())).collect();
+
+        let handle = utils::run_cmd_with_log_file(
+            &script.to_string_lossy(),
+            args,
+            &env,
+            deploy_logs::current(),
+        )?;
+        Self::set_script_pid(handle.id());
+        let result = handle.wait().await;
+        Self::set_script_pid(None);
+        result
+    }
+
+    fn set_script_pid(pid: Option<u32>) {
+        STATE.write().unwrap().deploy_script_pid = pid;
+    }
+
+    /// Runs a deploy script as one step of `phase`, within that phase's time limit.
+    async fn run_step_script(
+        phase: JobPhase,
+        name: &str,
+        args: &[&str],
+    ) -> Result<(), DeployError> {
+        Self::with_timeout(phase, async {
+            Self::run_script(name, args)
+                .await
+                .map_err(|e| DeployError::command(phase, e))
+        })
+        .await
+    }
+
+    /// Runs a deploy step under the time limit configured for its phase.
+    /// A step that runs out of time has its script killed and fails the deploy.
+    async fn with_timeout<T>(
+        phase: JobPhase,
+        step: impl Future<Output = Result<T, DeployError>>,
+    ) -> Result<T, DeployError> {
+        let Some(limit) = Self::phase_timeout(phase) else {
+            return step.await;
+        };
+
+        match tokio::time::timeout(limit, step).await {
+            Ok(result) => result,
+            Err(_) => {
+                let pid = STATE.write().unwrap().deploy_script_pid.take();
+                if let Some(pid) = pid {
+                    utils::kill_process_group(pid);
+                }
+                tracing::error!(
+                    target: "supervisor",
+                    phase = phase.as_str(),
+                    limit_secs = limit.as_secs(),
+                    "deploy phase timed out"
+                );
+                Err(DeployError::Timeout { phase, limit })
+            }
+        }
+    }
+
+    fn phase_timeout(phase: JobPhase) -> Option<std::time::Duration> {
+        let timeouts = &config::get().timeouts;
+        let secs = match phase {
+            JobPhase::PullingChanges => timeouts.pull_secs,
+            JobPhase::InstallingDependencies => timeouts.install_secs,
+            JobPhase::Building => timeouts.build_secs,
+            JobPhase::MovingBuild => timeouts.move_secs,
+            JobPhase::StartingInstance | JobPhase::HealthChecking => timeouts.start_secs,
+            _ => 0,
+        };
+        (secs > 0).then(|| Duration::from_secs(secs))
+    }
+
+    /// Waits for a freshly started instance to pass its health checks, within the start time limit.
+    async fn wait_until_healthy(instance_number: &str) -> Result<(), DeployError> {
+        Self::with_timeout(JobPhase::StartingInstance, async {
+            let config = config::get();
+            let backend = config.instance_backend(instance_number);
+            if health::wait_until_healthy(&backend, &config.health_check()).await {
+                Ok(())
+            } else {
+                Err(DeployError::HealthCheck {
+                    instance: instance_number.to_string(),
+                })
+            }
+        })
+        .await
+    }
+
+    /// Stores the build running in an instance as a release and prunes old ones.
+    async fn archive_release(instance_number: &str, commit: &str) {
+        if let Err(e) = Self::run_script("archive_release.sh", &[instance_number, commit]).await {
+            eprintln!("Error archiving release {}: {}", commit, e);
+            return;
+        }
+
+        let keep_releases = config::get().releases.keep.to_string();
+        if let Err(e) = Self::run_script("prune_releases.sh", &[keep_releases.as_str()]).await {
+            eprintln!("Error pruning old releases: {}", e);
+        }
+    }
+
+    async fn cleanup_instance(instance_number: &str) -> Result<(), CommandError> {
+        BuildInfo::remove(instance_number);
+        Self::run_script("cleanup_instance.sh", &[instance_number]).await
+    }
+
+    async fn cleanup_instances() -> Result<(), CommandError> {
+        BuildInfo::remove("1");
+        BuildInfo::remove("2");
+        Self::run_script("cleanup_instances.sh", &[]).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn cancel_before_start_does_not_strand_later_updates() {
+        let job_id = {
+            let mut state = STATE.write().unwrap();
+            let job_id = InstanceHandler::insert_job(&mut state, JobKind::Update, Trigger::Api);
+            state.pending_update = Some(PendingUpdate {
+                job_id,
+                git_ref: None,
+                expected_commit: None,
+                force: false,
+                last_requested_at: Instant::now(),
+            });
+            job_id
+        };
+
+        let (pending, cancel, _) =
+            InstanceHandler::start_running_job(job_id, &DeployAction::Update);
+        assert_eq!(pending.map(|pending| pending.job_id), Some(job_id));
+
+        // the cancel stores a permit, so it wins the race before the deploy is ever polled
+        assert_eq!(InstanceHandler::cancel_deploy().await.ok(), Some(job_id));
+        cancel.notified().await;
+        STATE.write().unwrap().running_job = None;
+
+        let next_job_id = InstanceHandler::request_update(None, None, false, Trigger::Api);
+        assert_ne!(next_job_id, job_id);
+    }
 }
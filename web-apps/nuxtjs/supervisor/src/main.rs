@@ -1,9 +1,20 @@
 // import start_api from ./api.ra
 pub mod api;
+pub mod build_info;
+pub mod config;
+pub mod deploy_logs;
+pub mod git;
+pub mod health;
+pub mod history;
 pub mod instance_handler;
+pub mod poller;
+pub mod preview;
 pub mod proxy;
-pub mod utils;
+pub mod releases;
 pub mod runtime_cli;
+pub mod shadow;
+pub mod utils;
+pub mod webhook;
 
 use tracing_subscriber::EnvFilter;
 use tracing_subscriber::fmt::format::FmtSpan;
@@ -21,15 +32,24 @@ fn init_tracing() {
 async fn main() {
     init_tracing();
 
-    instance_handler::InstanceHandler::startup().await;
-
-    tokio::spawn(runtime_cli::start());
+    if let Err(err) = config::init() {
+        tracing::error!(target: "supervisor", "{err}");
+        eprintln!("[supervisor] Invalid configuration: {err}");
+        std::process::exit(1);
+    }
 
-    let proxy_task = tokio::task::spawn_blocking(|| proxy::start_proxy());
+    // serve the unavailable page right away, the first build can take minutes
+    let proxy_task = tokio::task::spawn_blocking(proxy::start_proxy);
     let api_task = tokio::spawn(async {
         api::start_api().await;
     });
 
+    tokio::spawn(async {
+        instance_handler::InstanceHandler::startup().await;
+        runtime_cli::start().await;
+    });
+    tokio::spawn(poller::start());
+
     tracing::info!(target: "supervisor", "supervisor started successfully");
 
     tokio::select! {
@@ -0,0 +1,60 @@
+use crate::config;
+use crate::git;
+use crate::instance_handler::{InstanceHandler, Trigger};
+use tokio::time::MissedTickBehavior;
+
+/// Deploys new commits on the tracked branch by polling the git remote,
+/// for servers that webhooks cannot reach. Does nothing unless an interval is configured,
+/// and pauses while a deploy of a specific ref is live.
+pub async fn start() {
+    let Some(interval) = config::get().poll_interval() else {
+        return;
+    };
+
+    tracing::info!(
+        target: "supervisor",
+        interval_secs = interval.as_secs(),
+        "polling git remote for changes"
+    );
+
+    // the last head a poll deployed, so a commit that fails to deploy isn't retried on every poll
+    let mut last_attempted: Option<String> = None;
+
+    let mut ticker = tokio::time::interval(interval);
+    ticker.set_missed_tick_behavior(MissedTickBehavior::Delay);
+    // the first tick fires right away, startup already deploys the current head
+    ticker.tick().await;
+
+    loop {
+        ticker.tick().await;
+
+        let status = InstanceHandler::status_snapshot();
+        // a pinned ref stays deployed until an update without a ref goes back to the branch
+        if status.update_in_progress || status.deployed_ref.is_some() {
+            continue;
+        }
+
+        let head = match git::remote_head().await {
+            Ok(head) => head,
+            Err(e) => {
+                tracing::warn!(target: "supervisor", error = %e, "polling git remote failed");
+                continue;
+            }
+        };
+
+        if status.deployed_commit.as_deref() == Some(head.as_str())
+            || last_attempted.as_deref() == Some(head.as_str())
+        {
+            continue;
+        }
+
+        tracing::info!(
+            target: "supervisor",
+            commit = %head,
+            deployed = status.deployed_commit.as_deref().unwrap_or_default(),
+            "remote branch moved, starting update"
+        );
+        last_attempted = Some(head);
+        InstanceHandler::on_update(None, false, Trigger::Poll).await;
+    }
+}
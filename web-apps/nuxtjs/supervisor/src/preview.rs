@@ -0,0 +1,63 @@
+use crate::config;
+use crate::utils;
+
+/// Request header that carries a preview token.
+pub const SLOT_HEADER: &str = "x-supervisor-slot";
+/// Cookie set by `/_supervisor/preview` that carries a preview token.
+pub const COOKIE_NAME: &str = "supervisor_preview";
+
+/// A signed `<expires_at>.<hex hmac>` token that sends requests to the standby slot.
+pub struct PreviewToken {
+    pub token: String,
+    pub expires_at: u64,
+}
+
+/// Issues a token valid for the configured time, `None` without an API key to sign it with.
+pub fn issue() -> Option<PreviewToken> {
+    let expires_at = utils::unix_timestamp() + config::get().preview.ttl_secs;
+    let signature = utils::hmac_sha256(signing_key()?, signed_message(expires_at).as_bytes())?;
+    let signature = hex::encode(signature);
+
+    Some(PreviewToken {
+        token: format!("{expires_at}.{signature}"),
+        expires_at,
+    })
+}
+
+/// Whether a token was signed with the current API key and has not expired.
+pub fn verify(token: &str) -> bool {
+    let Some((expires_at, signature)) = token.trim().split_once('.') else {
+        return false;
+    };
+    let Ok(expires_at) = expires_at.parse::<u64>() else {
+        return false;
+    };
+    if expires_at < utils::unix_timestamp() {
+        return false;
+    }
+    let Ok(signature) = hex::decode(signature) else {
+        return false;
+    };
+    let Some(key) = signing_key() else {
+        return false;
+    };
+    utils::verify_hmac_sha256(key, signed_message(expires_at).as_bytes(), &signature)
+}
+
+/// Picks the preview token out of a `Cookie` header.
+pub fn token_from_cookie(cookie_header: &str) -> Option<&str> {
+    utils::cookie_value(cookie_header, COOKIE_NAME)
+}
+
+fn signing_key() -> Option<&'static [u8]> {
+    config::get()
+        .api
+        .api_key
+        .as_deref()
+        .filter(|key| !key.is_empty())
+        .map(str::as_bytes)
+}
+
+fn signed_message(expires_at: u64) -> String {
+    format!("supervisor-preview:{expires_at}")
+}
@@ -1,25 +1,127 @@
 use std::{
+    collections::HashMap,
     net::ToSocketAddrs,
-    sync::{Arc, RwLock},
+    sync::{
+        Arc, Mutex, RwLock,
+        atomic::{AtomicBool, Ordering},
+    },
+    time::Instant,
 };
 
 use async_trait::async_trait;
+use bytes::Bytes;
 use once_cell::sync::Lazy;
+use pingora::http::{RequestHeader, ResponseHeader};
 use pingora::prelude::*;
 use pingora::upstreams::peer::Peer;
 
-const DEFAULT_LISTEN_ADDR: &str = "0.0.0.0:19130";
-const DEFAULT_WORLD_BACKEND: &str = "127.0.0.1:19131";
-const SUPERVISOR_BACKEND: &str = "127.0.0.1:19180";
+use crate::config;
+use crate::preview;
+use crate::shadow;
+use crate::utils;
 
 static WORLD_BACKEND: Lazy<Arc<RwLock<HttpPeer>>> = Lazy::new(|| {
     Arc::new(RwLock::new(HttpPeer::new(
-        DEFAULT_WORLD_BACKEND,
+        config::get().instance_backend("1"),
         false,
         String::new(),
     )))
 });
 
+/// A new instance that gets a share of the world traffic before it is switched to.
+struct Canary {
+    peer: HttpPeer,
+    percent: u8,
+}
+
+static CANARY: Lazy<RwLock<Option<Canary>>> = Lazy::new(|| RwLock::new(None));
+
+// healthy instance in the standby slot, reachable with a preview token
+static PREVIEW_BACKEND: Lazy<RwLock<Option<HttpPeer>>> = Lazy::new(|| RwLock::new(None));
+
+// hands clients new to the canary the next of 100 buckets
+static CANARY_BUCKETS: utils::Sampler = utils::Sampler::new();
+
+// pins a client to its bucket, so a page and its assets come from the same slot for the whole canary
+const CANARY_COOKIE: &str = "supervisor_canary";
+
+// set once a backend has passed its health checks and been switched to
+static BACKEND_READY: AtomicBool = AtomicBool::new(false);
+
+const DEFAULT_UNAVAILABLE_PAGE: &str = "<!DOCTYPE html>
+<html>
+<head><meta charset=\"utf-8\"><title>Deploying</title></head>
+<body style=\"font-family: sans-serif; text-align: center; margin-top: 20vh;\">
+<h1>Deploying&hellip;</h1>
+<p>This site is being deployed and will be available shortly.</p>
+</body>
+</html>
+";
+
+static UNAVAILABLE_PAGE: Lazy<Bytes> =
+    Lazy::new(|| match config::get().proxy.unavailable_page.as_ref() {
+        Some(path) => match std::fs::read(path) {
+            Ok(page) => Bytes::from(page),
+            Err(err) => {
+                tracing::warn!(
+                    target: "supervisor",
+                    "could not read unavailable page {}: {err}",
+                    path.display()
+                );
+                Bytes::from_static(DEFAULT_UNAVAILABLE_PAGE.as_bytes())
+            }
+        },
+        None => Bytes::from_static(DEFAULT_UNAVAILABLE_PAGE.as_bytes()),
+    });
+
+// number of requests currently being proxied, keyed by backend address
+static IN_FLIGHT: Lazy<Mutex<HashMap<String, usize>>> = Lazy::new(|| Mutex::new(HashMap::new()));
+
+// outcome counts of finished requests, keyed by backend address
+static BACKEND_STATS: Lazy<Mutex<HashMap<String, BackendStats>>> =
+    Lazy::new(|| Mutex::new(HashMap::new()));
+
+/// How the requests proxied to one backend went since its counters were last reset.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct BackendStats {
+    pub requests: u64,
+    /// Responses with a 5xx status from the backend.
+    pub server_errors: u64,
+    /// Requests that never reached the backend because connecting failed.
+    pub connect_failures: u64,
+}
+
+impl BackendStats {
+    pub fn errors(&self) -> u64 {
+        self.server_errors + self.connect_failures
+    }
+
+    /// Share of failed requests in percent, 0 without requests.
+    pub fn error_percent(&self) -> f64 {
+        if self.requests == 0 {
+            return 0.0;
+        }
+        self.errors() as f64 * 100.0 / self.requests as f64
+    }
+}
+
+/// Per-request state carried through the proxy phases.
+#[derive(Default)]
+pub struct RequestCtx {
+    /// World backend this request was counted against, if any.
+    backend: Option<String>,
+    /// Status the backend answered with.
+    upstream_status: Option<u16>,
+    connect_failed: bool,
+    /// The request carried a valid preview token and went to the standby slot.
+    preview: bool,
+    /// The request went to the main instance and may be mirrored to the standby slot.
+    mirrorable: bool,
+    /// Canary bucket assigned to a client that did not have one yet, sent back as a cookie.
+    new_canary_bucket: Option<u8>,
+    started_at: Option<Instant>,
+}
+
 #[derive(Clone)]
 pub struct SupervisorProxy {
     world_backend: Arc<RwLock<HttpPeer>>,
@@ -41,18 +143,73 @@ impl SupervisorProxy {
             .map_err(|_| Error::new(ErrorType::InternalError))?;
         Ok(Box::new(guard.clone()))
     }
+
+    /// The standby backend, if one is up and the request carries a valid preview token.
+    fn preview_peer(&self, session: &Session) -> Option<Box<HttpPeer>> {
+        let peer = PREVIEW_BACKEND.read().ok()?.clone()?;
+        let headers = &session.req_header().headers;
+
+        let header_token = headers
+            .get(preview::SLOT_HEADER)
+            .and_then(|value| value.to_str().ok());
+        let cookie_tokens = headers
+            .get_all("cookie")
+            .iter()
+            .filter_map(|value| value.to_str().ok())
+            .filter_map(preview::token_from_cookie);
+
+        let mut tokens = header_token.into_iter().chain(cookie_tokens);
+        tokens.any(preview::verify).then(|| Box::new(peer))
+    }
+
+    /// The canary backend, if one is set and the client's bucket falls into its share.
+    /// Clients keep their bucket while the share grows, so they only ever move to the canary.
+    fn canary_peer(&self, session: &Session, ctx: &mut RequestCtx) -> Option<Box<HttpPeer>> {
+        let guard = CANARY.read().ok()?;
+        let canary = guard.as_ref()?;
+
+        let cookie_bucket = session
+            .req_header()
+            .headers
+            .get_all("cookie")
+            .iter()
+            .filter_map(|value| value.to_str().ok())
+            .filter_map(|cookie| utils::cookie_value(cookie, CANARY_COOKIE))
+            .find_map(|bucket| bucket.parse::<u8>().ok().filter(|bucket| *bucket < 100));
+        // a retried request keeps the bucket it was given on the first attempt
+        let bucket = cookie_bucket.or(ctx.new_canary_bucket).unwrap_or_else(|| {
+            let bucket = CANARY_BUCKETS.next_bucket();
+            ctx.new_canary_bucket = Some(bucket);
+            bucket
+        });
+
+        (bucket < canary.percent).then(|| Box::new(canary.peer.clone()))
+    }
 }
 
 #[async_trait]
 impl ProxyHttp for SupervisorProxy {
-    type CTX = ();
+    type CTX = RequestCtx;
+
+    fn new_ctx(&self) -> Self::CTX {
+        RequestCtx::default()
+    }
 
-    fn new_ctx(&self) -> Self::CTX {}
+    async fn request_filter(&self, session: &mut Session, _ctx: &mut Self::CTX) -> Result<bool> {
+        if BACKEND_READY.load(Ordering::Acquire)
+            || session.req_header().uri.path().starts_with("/_supervisor")
+        {
+            return Ok(false);
+        }
+
+        write_unavailable_page(session).await?;
+        Ok(true)
+    }
 
     async fn upstream_peer(
         &self,
         session: &mut Session,
-        _ctx: &mut Self::CTX,
+        ctx: &mut Self::CTX,
     ) -> Result<Box<HttpPeer>> {
         let path = session.req_header().uri.path();
 
@@ -61,23 +218,121 @@ impl ProxyHttp for SupervisorProxy {
             return Ok(Box::new(self.supervisor_backend.clone()));
         }
 
-        self.current_world_peer()
+        let peer = if let Some(peer) = self.preview_peer(session) {
+            ctx.preview = true;
+            peer
+        } else if let Some(peer) = self.canary_peer(session, ctx) {
+            peer
+        } else {
+            ctx.mirrorable = true;
+            self.current_world_peer()?
+        };
+        if ctx.backend.is_none() {
+            ctx.started_at = Some(Instant::now());
+            let addr = peer.address().to_string();
+            track_request_start(&addr);
+            ctx.backend = Some(addr);
+        }
+        Ok(peer)
+    }
+
+    async fn upstream_request_filter(
+        &self,
+        _session: &mut Session,
+        upstream_request: &mut RequestHeader,
+        _ctx: &mut Self::CTX,
+    ) -> Result<()> {
+        // the preview token only picks the slot, the app never gets to see or replay it
+        upstream_request.remove_header(preview::SLOT_HEADER);
+
+        let carries_token = upstream_request
+            .headers
+            .get_all("cookie")
+            .iter()
+            .filter_map(|value| value.to_str().ok())
+            .any(|cookie| preview::token_from_cookie(cookie).is_some());
+        if !carries_token {
+            return Ok(());
+        }
+
+        let cookies: Vec<String> = upstream_request
+            .headers
+            .get_all("cookie")
+            .iter()
+            .filter_map(|value| value.to_str().ok())
+            .map(|cookie| utils::without_cookie(cookie, preview::COOKIE_NAME))
+            .filter(|cookie| !cookie.is_empty())
+            .collect();
+        upstream_request.remove_header("cookie");
+        for cookie in cookies {
+            upstream_request.append_header("cookie", cookie)?;
+        }
+        Ok(())
+    }
+
+    async fn response_filter(
+        &self,
+        _session: &mut Session,
+        upstream_response: &mut ResponseHeader,
+        ctx: &mut Self::CTX,
+    ) -> Result<()> {
+        ctx.upstream_status = Some(upstream_response.status.as_u16());
+        if ctx.preview {
+            upstream_response.insert_header("X-Supervisor-Slot", "standby")?;
+        }
+        if let Some(bucket) = ctx.new_canary_bucket {
+            let cookie = format!("{CANARY_COOKIE}={bucket}; Path=/; HttpOnly; SameSite=Lax");
+            upstream_response.append_header("Set-Cookie", cookie)?;
+        }
+        Ok(())
+    }
+
+    fn fail_to_connect(
+        &self,
+        _session: &mut Session,
+        _peer: &HttpPeer,
+        ctx: &mut Self::CTX,
+        e: Box<Error>,
+    ) -> Box<Error> {
+        ctx.connect_failed = true;
+        e
+    }
+
+    async fn logging(&self, session: &mut Session, _e: Option<&Error>, ctx: &mut Self::CTX) {
+        if let Some(addr) = ctx.backend.take() {
+            track_request_end(&addr);
+            let server_error = ctx.upstream_status.is_some_and(|status| status >= 500);
+            record_outcome(&addr, server_error, ctx.connect_failed);
+        }
+
+        let req = session.req_header();
+        if ctx.mirrorable
+            && req.method.as_str() == "GET"
+            && let (Some(status), Some(started_at)) = (ctx.upstream_status, ctx.started_at)
+        {
+            let path = req
+                .uri
+                .path_and_query()
+                .map(|path| path.as_str())
+                .unwrap_or("/");
+            shadow::mirror(path, &req.headers, status, started_at.elapsed());
+        }
     }
 }
 
 pub fn start_proxy() -> Result<()> {
-    let listen_addr = std::env::var("SUPERVISOR_PROXY_LISTEN")
-        .unwrap_or_else(|_| DEFAULT_LISTEN_ADDR.to_string());
+    let config = config::get();
+    let listen_addr = config.proxy.listen.as_str();
 
     let world_backend = WORLD_BACKEND.clone();
-    let supervisor_backend = HttpPeer::new(SUPERVISOR_BACKEND, false, String::new());
+    let supervisor_backend = HttpPeer::new(config.api.listen.as_str(), false, String::new());
     let app = SupervisorProxy::new(world_backend, supervisor_backend);
 
     let mut server = Server::new(None)?;
     server.bootstrap();
 
     let mut proxy_service = http_proxy_service(&server.configuration, app);
-    proxy_service.add_tcp(&listen_addr);
+    proxy_service.add_tcp(listen_addr);
     tracing::info!(target: "supervisor", "pingora reverse proxy listening on {listen_addr}");
 
     server.add_service(proxy_service);
@@ -91,10 +346,101 @@ pub fn set_world_backend(addr: &str) -> Result<()> {
         .write()
         .map_err(|_| Error::new(ErrorType::InternalError))?;
     *guard = peer;
+    BACKEND_READY.store(true, Ordering::Release);
     tracing::info!(target: "supervisor", "world backend updated to {addr}");
     Ok(())
 }
 
+/// Lets requests with a preview token reach `addr`, the healthy instance in the standby slot.
+pub fn set_preview_backend(addr: &str) -> Result<()> {
+    validate_backend(addr)?;
+    let mut guard = PREVIEW_BACKEND
+        .write()
+        .map_err(|_| Error::new(ErrorType::InternalError))?;
+    *guard = Some(HttpPeer::new(addr, false, String::new()));
+    tracing::info!(target: "supervisor", "preview backend set to {addr}");
+    Ok(())
+}
+
+/// Stops preview routing to `addr`, if it is the preview backend.
+pub fn remove_preview_backend(addr: &str) {
+    if let Ok(mut guard) = PREVIEW_BACKEND.write()
+        && guard
+            .as_ref()
+            .is_some_and(|peer| peer.address().to_string() == addr)
+    {
+        *guard = None;
+        tracing::info!(target: "supervisor", "preview backend {addr} removed");
+    }
+}
+
+/// Address of the standby instance reachable with a preview token, if any.
+pub fn current_preview_backend() -> Option<String> {
+    PREVIEW_BACKEND
+        .read()
+        .ok()
+        .and_then(|peer| peer.as_ref().map(|peer| peer.address().to_string()))
+}
+
+/// Sends `percent` of the world traffic to `addr`, the rest keeps going to the world backend.
+pub fn set_canary_backend(addr: &str, percent: u8) -> Result<()> {
+    validate_backend(addr)?;
+    let mut guard = CANARY
+        .write()
+        .map_err(|_| Error::new(ErrorType::InternalError))?;
+    *guard = Some(Canary {
+        peer: HttpPeer::new(addr, false, String::new()),
+        percent: percent.min(100),
+    });
+    tracing::info!(target: "supervisor", "canary backend {addr} receives {percent}% of requests");
+    Ok(())
+}
+
+/// Changes the share of the traffic the canary backend gets.
+pub fn set_canary_percent(percent: u8) {
+    if let Ok(mut guard) = CANARY.write()
+        && let Some(canary) = guard.as_mut()
+    {
+        canary.percent = percent.min(100);
+        tracing::info!(
+            target: "supervisor",
+            "canary backend {} receives {percent}% of requests",
+            canary.peer.address()
+        );
+    }
+}
+
+/// Stops sending traffic to the canary backend.
+pub fn clear_canary() {
+    if let Ok(mut guard) = CANARY.write()
+        && guard.take().is_some()
+    {
+        tracing::info!(target: "supervisor", "canary backend removed");
+    }
+}
+
+/// Whether a healthy backend is receiving traffic yet.
+pub fn backend_ready() -> bool {
+    BACKEND_READY.load(Ordering::Acquire)
+}
+
+async fn write_unavailable_page(session: &mut Session) -> Result<()> {
+    let body = UNAVAILABLE_PAGE.clone();
+    let retry_after = config::get().proxy.retry_after_secs.to_string();
+
+    let mut header = ResponseHeader::build(503, Some(4))?;
+    header.insert_header("Content-Type", "text/html; charset=utf-8")?;
+    header.insert_header("Content-Length", body.len().to_string())?;
+    header.insert_header("Retry-After", retry_after)?;
+    header.insert_header("Cache-Control", "no-store")?;
+
+    session
+        .write_response_header(Box::new(header), false)
+        .await?;
+    session.write_response_body(Some(body), true).await?;
+    Ok(())
+}
+
 pub fn current_world_backend() -> Option<String> {
     WORLD_BACKEND
         .read()
@@ -102,6 +448,57 @@ pub fn current_world_backend() -> Option<String> {
         .map(|peer| peer.address().to_string())
 }
 
+/// Number of requests currently in flight to the given backend address.
+pub fn active_requests(addr: &str) -> usize {
+    IN_FLIGHT
+        .lock()
+        .ok()
+        .and_then(|counts| counts.get(addr).copied())
+        .unwrap_or(0)
+}
+
+/// Request outcomes of the given backend address since its counters were last reset.
+pub fn backend_stats(addr: &str) -> BackendStats {
+    BACKEND_STATS
+        .lock()
+        .ok()
+        .and_then(|stats| stats.get(addr).copied())
+        .unwrap_or_default()
+}
+
+/// Starts counting the request outcomes of the given backend address from zero.
+pub fn reset_backend_stats(addr: &str) {
+    if let Ok(mut stats) = BACKEND_STATS.lock() {
+        stats.remove(addr);
+    }
+}
+
+fn record_outcome(addr: &str, server_error: bool, connect_failed: bool) {
+    if let Ok(mut stats) = BACKEND_STATS.lock() {
+        let entry = stats.entry(addr.to_string()).or_default();
+        entry.requests += 1;
+        if connect_failed {
+            entry.connect_failures += 1;
+        } else if server_error {
+            entry.server_errors += 1;
+        }
+    }
+}
+
+fn track_request_start(addr: &str) {
+    if let Ok(mut counts) = IN_FLIGHT.lock() {
+        *counts.entry(addr.to_string()).or_insert(0) += 1;
+    }
+}
+
+fn track_request_end(addr: &str) {
+    if let Ok(mut counts) = IN_FLIGHT.lock()
+        && let Some(count) = counts.get_mut(addr)
+    {
+        *count = count.saturating_sub(1);
+    }
+}
+
 fn validate_backend(addr: &str) -> Result<()> {
     addr.to_socket_addrs()
         .map_err(|_| Error::new(ErrorType::InternalError))?
@@ -0,0 +1,125 @@
+use crate::config;
+use serde::Serialize;
+use std::time::UNIX_EPOCH;
+
+// written into each release by archive_release.sh, dotfiles are not copied back into instances
+const CREATED_AT_FILE: &str = ".created_at";
+
+#[derive(Clone, Debug, Serialize)]
+pub struct Release {
+    pub commit: String,
+    pub created_at: u64,
+}
+
+/// Lists the stored releases, newest first.
+pub fn list() -> Vec<Release> {
+    let Ok(entries) = std::fs::read_dir(config::get().releases_dir()) else {
+        return Vec::new();
+    };
+
+    let mut releases: Vec<Release> = entries
+        .filter_map(|entry| entry.ok())
+        .filter(|entry| entry.file_type().map(|t| t.is_dir()).unwrap_or(false))
+        .map(|entry| {
+            // archive_release.sh stamps each release, older releases fall back to the directory's mtime
+            let created_at = std::fs::read_to_string(entry.path().join(CREATED_AT_FILE))
+                .ok()
+                .and_then(|stamp| stamp.trim().parse().ok())
+                .or_else(|| {
+                    entry
+                        .metadata()
+                        .and_then(|m| m.modified())
+                        .ok()
+                        .and_then(|t| t.duration_since(UNIX_EPOCH).ok())
+                        .map(|d| d.as_secs())
+                })
+                .unwrap_or(0);
+            Release {
+                commit: entry.file_name().to_string_lossy().to_string(),
+                created_at,
+            }
+        })
+        .collect();
+
+    releases.sort_by_key(|release| std::cmp::Reverse(release.created_at));
+    releases
+}
+
+/// Finds a release by full commit SHA or unique prefix.
+/// Without a commit, picks the release archived before `current_commit`.
+pub fn find(commit: Option<&str>, current_commit: Option<&str>) -> Option<Release> {
+    let releases = list();
+
+    match commit {
+        Some(commit) => {
+            let mut matches = releases
+                .into_iter()
+                .filter(|release| release.commit.starts_with(commit));
+            let found = matches.next()?;
+            // an ambiguous prefix is treated as not found
+            if matches.next().is_some() {
+                return None;
+            }
+            Some(found)
+        }
+        None => previous(releases, current_commit),
+    }
+}
+
+/// The newest release archived before `current_commit`, so repeated rollbacks keep going back.
+/// If `current_commit` has no release, the newest release is used.
+fn previous(releases: Vec<Release>, current_commit: Option<&str>) -> Option<Release> {
+    let current = current_commit
+        .and_then(|commit| releases.iter().position(|release| release.commit == commit));
+
+    match current {
+        Some(position) => releases.into_iter().nth(position + 1),
+        None => releases.into_iter().next(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn releases(commits: &[&str]) -> Vec<Release> {
+        let newest = commits.len() as u64;
+        commits
+            .iter()
+            .enumerate()
+            .map(|(i, commit)| Release {
+                commit: commit.to_string(),
+                created_at: newest - i as u64,
+            })
+            .collect()
+    }
+
+    fn previous_commit(commits: &[&str], current: Option<&str>) -> Option<String> {
+        previous(releases(commits), current).map(|release| release.commit)
+    }
+
+    #[test]
+    fn two_rollbacks_in_a_row_keep_going_back() {
+        let commits = ["c3", "c2", "c1"];
+
+        let first = previous_commit(&commits, Some("c3"));
+        assert_eq!(first.as_deref(), Some("c2"));
+
+        let second = previous_commit(&commits, first.as_deref());
+        assert_eq!(second.as_deref(), Some("c1"));
+    }
+
+    #[test]
+    fn nothing_older_than_the_oldest_release() {
+        assert_eq!(previous_commit(&["c2", "c1"], Some("c1")), None);
+    }
+
+    #[test]
+    fn unknown_deployed_commit_uses_the_newest_release() {
+        assert_eq!(
+            previous_commit(&["c2", "c1"], Some("c9")).as_deref(),
+            Some("c2")
+        );
+        assert_eq!(previous_commit(&["c2", "c1"], None).as_deref(), Some("c2"));
+    }
+}
@@ -1,5 +1,9 @@
-use crate::instance_handler::{InstanceHandler, InstanceStatus};
+use crate::instance_handler::{
+    CandidateStatus, DeploymentJob, InstanceExit, InstanceHandler, InstanceStatus, JobOutcome,
+    Trigger,
+};
 use crate::proxy;
+use crate::shadow::ShadowSummary;
 use std::io::Write;
 use tokio::io::{self, AsyncBufReadExt, BufReader};
 
@@ -55,7 +59,28 @@ async fn handle_command(cmd_line: &str) {
         "instances" => print_instances(),
         "backend" => print_backend(),
         "queue" => print_queue(),
-        "update" => handle_update().await,
+        "update" => {
+            let args: Vec<&str> = parts.collect();
+            let force = args.contains(&"--force");
+            let git_ref = args.into_iter().find(|arg| *arg != "--force");
+            // deploys run in the background so 'cancel' can still be typed
+            tokio::spawn(handle_update(git_ref.map(str::to_string), force));
+        }
+        "releases" => print_releases(),
+        "rollback" => {
+            tokio::spawn(handle_rollback(parts.next().map(str::to_string)));
+        }
+        "cancel" => handle_cancel().await,
+        "promote" => handle_promote(),
+        "discard" => handle_discard(),
+        "canary" => match parts.next() {
+            None => print_candidate(),
+            Some("promote") => handle_promote(),
+            Some("abort") => handle_discard(),
+            Some(_) => println!("[supervisor] Usage: canary [promote|abort]"),
+        },
+        "history" => print_history(parts.next()),
+        "logs" => print_logs(parts.next(), parts.next()),
         "stop" | "shutdown" => handle_stop().await,
         other => println!("[supervisor] Unknown command '{other}'. Type 'help' for options."),
     }
@@ -68,7 +93,15 @@ fn print_help() {
     println!("  instances   Show instance-level information");
     println!("  backend     Show active world backend address");
     println!("  queue       Show update queue information");
-    println!("  update      Trigger an update sequence");
+    println!("  update [ref] [--force]  Trigger an update sequence (default: tracked branch)");
+    println!("  releases    List stored releases available for rollback");
+    println!("  rollback [sha]  Roll back to a stored release (default: previous)");
+    println!("  cancel      Cancel the running update or rollback");
+    println!("  promote     Switch to the new build awaiting promotion or being canaried");
+    println!("  discard     Drop the new build awaiting promotion or being canaried");
+    println!("  canary [promote|abort]  Show, promote or abort the running canary");
+    println!("  history [n] Show the last n recorded deployments (default: 10)");
+    println!("  logs deploy <id>  Show the build and deploy log of a deployment");
     println!("  stop        Stop both instances and exit the supervisor");
 }
 
@@ -93,28 +126,74 @@ fn print_status() {
         "[supervisor] Pending update requests: {}",
         status.queued_update_requests
     );
+    println!(
+        "[supervisor] Deployed commit: {}{}",
+        status.deployed_commit.as_deref().unwrap_or("(unknown)"),
+        status
+            .deployed_ref
+            .as_deref()
+            .map(|git_ref| format!(" ({git_ref})"))
+            .unwrap_or_default()
+    );
+    if let Some(candidate) = &status.candidate {
+        print_candidate_status(candidate);
+    }
+    if let Some(shadow) = &status.shadow {
+        println!("[supervisor] Shadow: {}", shadow_summary(shadow));
+    }
+    match &status.last_deploy {
+        Some(job) => println!(
+            "[supervisor] Last deploy: job #{} {}{}",
+            job.id,
+            job.outcome.map(|o| o.as_str()).unwrap_or("running"),
+            job.error
+                .as_deref()
+                .map(|e| format!(" ({e})"))
+                .unwrap_or_default()
+        ),
+        None => println!("[supervisor] Last deploy: (none)"),
+    }
 }
 
 fn print_instances() {
     let status = InstanceHandler::status_snapshot();
-    println!(
-        "[supervisor] Instance #1: {}",
-        if status.instance1_running {
-            "running"
-        } else {
-            "stopped"
-        }
+    print_instance(
+        "1",
+        status.instance1_running,
+        status.instance1_failed,
+        &status.instance1_last_exit,
     );
-    println!(
-        "[supervisor] Instance #2: {}",
-        if status.instance2_running {
-            "running"
-        } else {
-            "stopped"
-        }
+    print_instance(
+        "2",
+        status.instance2_running,
+        status.instance2_failed,
+        &status.instance2_last_exit,
     );
 }
 
+fn print_instance(number: &str, running: bool, failed: bool, last_exit: &Option<InstanceExit>) {
+    let state = if failed {
+        "failed (restarts exhausted)"
+    } else if running {
+        "running"
+    } else {
+        "stopped"
+    };
+    println!("[supervisor] Instance #{number}: {state}");
+
+    if let Some(exit) = last_exit {
+        let reason = match (exit.code, exit.signal) {
+            (Some(code), _) => format!("exit code {code}"),
+            (None, Some(signal)) => format!("signal {signal}"),
+            (None, None) => "unknown reason".to_string(),
+        };
+        println!(
+            "[supervisor]   last crash: {reason} at {} (unix time)",
+            exit.exited_at
+        );
+    }
+}
+
 fn print_backend() {
     match proxy::current_world_backend() {
         Some(addr) => println!("[supervisor] Active world backend: {addr}"),
@@ -138,16 +217,220 @@ async fn handle_stop() {
     std::process::exit(0);
 }
 
-async fn handle_update() {
+async fn handle_update(git_ref: Option<String>, force: bool) {
     println!("[supervisor] Update requested. Starting update sequence...");
-    InstanceHandler::on_update().await;
-    println!("[supervisor] Update sequence completed.");
+    match InstanceHandler::on_update(git_ref, force, Trigger::Cli).await {
+        Some(job) if job.error.is_some() => println!(
+            "[supervisor] Update failed: {}",
+            job.error.unwrap_or_default()
+        ),
+        Some(job) if job.outcome == Some(JobOutcome::Skipped) => println!(
+            "[supervisor] {} is already deployed, nothing to do. Use 'update --force' to rebuild.",
+            job.commit.as_deref().unwrap_or("(unknown)")
+        ),
+        Some(job) => println!(
+            "[supervisor] Update completed: {} -> {}{}",
+            job.previous_commit.as_deref().unwrap_or("(unknown)"),
+            job.commit.as_deref().unwrap_or("(unknown)"),
+            if job.force_pushed {
+                " (force-pushed)"
+            } else {
+                ""
+            }
+        ),
+        None => println!("[supervisor] Update sequence completed."),
+    }
 }
 
-fn bool_to_icon(flag: bool) -> &'static str {
-    if flag {
-        "yes"
-    } else {
-        "no"
+fn print_releases() {
+    let status = InstanceHandler::status_snapshot();
+    let releases = InstanceHandler::releases();
+    if releases.is_empty() {
+        println!("[supervisor] No stored releases.");
+        return;
+    }
+
+    println!("[supervisor] Stored releases (newest first):");
+    for release in releases {
+        let marker = if status.deployed_commit.as_deref() == Some(release.commit.as_str()) {
+            " (deployed)"
+        } else {
+            ""
+        };
+        println!("  {}{marker}", release.commit);
+    }
+}
+
+async fn handle_rollback(commit: Option<String>) {
+    println!("[supervisor] Rollback requested. Starting rollback sequence...");
+    match InstanceHandler::on_rollback(commit, Trigger::Cli).await {
+        Some(job) if job.error.is_some() => println!(
+            "[supervisor] Rollback failed: {}",
+            job.error.unwrap_or_default()
+        ),
+        Some(job) => println!(
+            "[supervisor] Rollback to {} completed.",
+            job.commit.as_deref().unwrap_or("(unknown)")
+        ),
+        None => println!("[supervisor] Rollback sequence completed."),
+    }
+}
+
+async fn handle_cancel() {
+    match InstanceHandler::cancel_deploy().await {
+        Ok(job_id) => println!(
+            "[supervisor] Cancelled deployment job #{job_id}. The main instance keeps serving."
+        ),
+        Err(err) => println!("[supervisor] Nothing cancelled: {err}"),
+    }
+}
+
+fn print_candidate() {
+    let status = InstanceHandler::status_snapshot();
+    match status.candidate {
+        Some(candidate) => print_candidate_status(&candidate),
+        None => println!("[supervisor] No new build is awaiting promotion or being canaried."),
+    }
+    if let Some(shadow) = &status.shadow {
+        println!("[supervisor] Shadow: {}", shadow_summary(shadow));
+        for example in &shadow.examples {
+            println!(
+                "  {} main {} new {}",
+                example.path,
+                example.main_status,
+                example
+                    .shadow_status
+                    .map(|status| status.to_string())
+                    .unwrap_or_else(|| "no answer".to_string())
+            );
+        }
+    }
+}
+
+fn print_candidate_status(candidate: &CandidateStatus) {
+    match candidate.canary_percent {
+        Some(percent) => println!(
+            "[supervisor] Canary: instance #{} receives {percent}% of requests (job #{})",
+            candidate.instance, candidate.job_id
+        ),
+        None => println!(
+            "[supervisor] Awaiting promotion: instance #{} (job #{}). Use 'promote' or 'discard'.",
+            candidate.instance, candidate.job_id
+        ),
+    }
+}
+
+fn handle_promote() {
+    match InstanceHandler::promote() {
+        Ok(job_id) => println!("[supervisor] Promoting the new build of job #{job_id}."),
+        Err(err) => println!("[supervisor] Nothing promoted: {err}"),
     }
 }
+
+fn handle_discard() {
+    match InstanceHandler::discard() {
+        Ok(job_id) => println!(
+            "[supervisor] Discarding the new build of job #{job_id}. The main instance keeps serving."
+        ),
+        Err(err) => println!("[supervisor] Nothing discarded: {err}"),
+    }
+}
+
+fn print_history(count: Option<&str>) {
+    let count = match count.map(str::parse::<usize>) {
+        None => 10,
+        Some(Ok(count)) => count,
+        Some(Err(_)) => {
+            println!("[supervisor] Usage: history [n]");
+            return;
+        }
+    };
+
+    let records = InstanceHandler::history(count);
+    if records.is_empty() {
+        println!("[supervisor] No recorded deployments.");
+        return;
+    }
+
+    println!("[supervisor] Recorded deployments (newest first):");
+    for job in records {
+        print_history_record(&job);
+    }
+}
+
+fn print_logs(kind: Option<&str>, id: Option<&str>) {
+    let job_id = match (kind, id.map(str::parse::<u64>)) {
+        (Some("deploy"), Some(Ok(job_id))) => job_id,
+        _ => {
+            println!("[supervisor] Usage: logs deploy <id>");
+            return;
+        }
+    };
+
+    match InstanceHandler::deploy_log(job_id) {
+        Some(log) => {
+            println!("[supervisor] Deploy log of job #{job_id}:");
+            print!("{log}");
+        }
+        None => println!("[supervisor] No deploy log for job #{job_id}."),
+    }
+}
+
+fn print_history_record(job: &DeploymentJob) {
+    let duration = match (job.started_at, job.finished_at) {
+        (Some(started), Some(finished)) => format!("{}s", finished.saturating_sub(started)),
+        _ => "-".to_string(),
+    };
+    println!(
+        "  #{} {} via {} {} in {} | {} -> {} | live: {}",
+        job.id,
+        job.kind.as_str(),
+        job.trigger.as_str(),
+        job.outcome.map(|o| o.as_str()).unwrap_or("running"),
+        duration,
+        short_commit(job.previous_commit.as_deref()),
+        short_commit(job.commit.as_deref()),
+        job.live_instance.as_deref().unwrap_or("-"),
+    );
+
+    let phases: Vec<String> = job
+        .phases
+        .iter()
+        .filter_map(|timing| {
+            let secs = timing.duration_ms? as f64 / 1000.0;
+            Some(format!("{} {secs:.1}s", timing.phase.as_str()))
+        })
+        .collect();
+    if !phases.is_empty() {
+        println!("      phases: {}", phases.join(", "));
+    }
+    if let Some(shadow) = &job.shadow {
+        println!("      shadow: {}", shadow_summary(shadow));
+    }
+    if let Some(error) = &job.error {
+        println!("      error: {error}");
+    }
+}
+
+fn shadow_summary(shadow: &ShadowSummary) -> String {
+    format!(
+        "{} mirrored, {} status mismatches, {} failed ({:.1}%), avg latency {}ms main / {}ms new",
+        shadow.samples + shadow.failures,
+        shadow.status_mismatches,
+        shadow.failures,
+        shadow.mismatch_percent(),
+        shadow.avg_main_latency_ms(),
+        shadow.avg_shadow_latency_ms()
+    )
+}
+
+fn short_commit(commit: Option<&str>) -> &str {
+    match commit {
+        Some(commit) => commit.get(..8).unwrap_or(commit),
+        None => "(unknown)",
+    }
+}
+
+fn bool_to_icon(flag: bool) -> &'static str {
+    if flag { "yes" } else { "no" }
+}
@@ -0,0 +1,204 @@
+use crate::config;
+use crate::preview;
+use crate::utils;
+use once_cell::sync::Lazy;
+use reqwest::header::{self, HeaderMap};
+use serde::{Deserialize, Serialize};
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::{Duration, Instant};
+
+// mirrored requests still running, further samples are skipped above this
+const MAX_IN_FLIGHT: usize = 32;
+const MIRROR_TIMEOUT: Duration = Duration::from_secs(10);
+// differing requests kept in the summary as examples
+const MAX_EXAMPLES: usize = 10;
+
+/// The standby instance live GET requests are currently mirrored to.
+struct Shadow {
+    addr: String,
+    sample_percent: u8,
+    stats: ShadowSummary,
+}
+
+static SHADOW: Lazy<Mutex<Option<Shadow>>> = Lazy::new(|| Mutex::new(None));
+
+static SAMPLER: utils::Sampler = utils::Sampler::new();
+static IN_FLIGHT: AtomicUsize = AtomicUsize::new(0);
+
+static CLIENT: Lazy<reqwest::Client> = Lazy::new(|| {
+    reqwest::Client::builder()
+        .timeout(MIRROR_TIMEOUT)
+        .redirect(reqwest::redirect::Policy::none())
+        .build()
+        .unwrap_or_default()
+});
+
+/// How the mirrored requests compared to the main instance's answers.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct ShadowSummary {
+    /// Requests mirrored and answered by both instances.
+    pub samples: u64,
+    /// Samples whose status class (2xx, 3xx, ...) differed between the instances.
+    pub status_mismatches: u64,
+    /// Mirrored requests the standby instance did not answer at all.
+    pub failures: u64,
+    pub main_latency_ms_total: u64,
+    pub shadow_latency_ms_total: u64,
+    pub examples: Vec<ShadowMismatch>,
+}
+
+/// One mirrored request the standby instance answered differently.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct ShadowMismatch {
+    pub path: String,
+    pub main_status: u16,
+    /// Unset if the standby instance did not answer.
+    pub shadow_status: Option<u16>,
+}
+
+impl ShadowSummary {
+    /// Share of the mirrored requests that differed or failed, in percent.
+    pub fn mismatch_percent(&self) -> f64 {
+        let total = self.samples + self.failures;
+        if total == 0 {
+            return 0.0;
+        }
+        (self.status_mismatches + self.failures) as f64 * 100.0 / total as f64
+    }
+
+    pub fn avg_main_latency_ms(&self) -> u64 {
+        self.main_latency_ms_total
+            .checked_div(self.samples)
+            .unwrap_or(0)
+    }
+
+    pub fn avg_shadow_latency_ms(&self) -> u64 {
+        self.shadow_latency_ms_total
+            .checked_div(self.samples)
+            .unwrap_or(0)
+    }
+}
+
+/// Starts mirroring the configured sample of GET requests to `addr`.
+pub fn start(addr: &str) {
+    let sample_percent = config::get().shadow.sample_percent;
+    if let Ok(mut shadow) = SHADOW.lock() {
+        *shadow = Some(Shadow {
+            addr: addr.to_string(),
+            sample_percent,
+            stats: ShadowSummary::default(),
+        });
+    }
+    tracing::info!(target: "supervisor", "mirroring {sample_percent}% of GET requests to {addr}");
+}
+
+/// Stops mirroring and returns what was collected, `None` if nothing was being mirrored.
+pub fn stop() -> Option<ShadowSummary> {
+    let shadow = SHADOW.lock().ok()?.take()?;
+    tracing::info!(target: "supervisor", "stopped mirroring requests to {}", shadow.addr);
+    Some(shadow.stats)
+}
+
+/// What has been collected so far, `None` if nothing is being mirrored.
+pub fn summary() -> Option<ShadowSummary> {
+    let shadow = SHADOW.lock().ok()?;
+    shadow.as_ref().map(|shadow| shadow.stats.clone())
+}
+
+/// Replays a GET request the main instance answered against the standby instance, if it is sampled.
+/// Runs in the background, the client never waits for it.
+pub fn mirror(path: &str, headers: &HeaderMap, main_status: u16, main_latency: Duration) {
+    let addr = {
+        let Ok(shadow) = SHADOW.lock() else {
+            return;
+        };
+        let Some(shadow) = shadow.as_ref() else {
+            return;
+        };
+        if !SAMPLER.sample(shadow.sample_percent) {
+            return;
+        }
+        shadow.addr.clone()
+    };
+
+    if IN_FLIGHT.fetch_add(1, Ordering::AcqRel) >= MAX_IN_FLIGHT {
+        IN_FLIGHT.fetch_sub(1, Ordering::AcqRel);
+        return;
+    }
+
+    let url = format!("http://{addr}{path}");
+    let path = path.to_string();
+    let headers = forwarded_headers(headers);
+    let main_latency_ms = main_latency.as_millis() as u64;
+
+    tokio::spawn(async move {
+        let started = Instant::now();
+        let result = async {
+            let response = CLIENT.get(&url).headers(headers).send().await?;
+            let status = response.status().as_u16();
+            // read the body so the latency covers the whole response, then drop it
+            response.bytes().await?;
+            Ok::<u16, reqwest::Error>(status)
+        }
+        .await;
+        let shadow_latency_ms = started.elapsed().as_millis() as u64;
+        IN_FLIGHT.fetch_sub(1, Ordering::AcqRel);
+
+        // the shadow may have been stopped or replaced while the request ran
+        let Ok(mut shadow) = SHADOW.lock() else {
+            return;
+        };
+        let Some(shadow) = shadow.as_mut().filter(|shadow| shadow.addr == addr) else {
+            return;
+        };
+        let stats = &mut shadow.stats;
+
+        let shadow_status = match result {
+            Ok(status) => {
+                stats.samples += 1;
+                stats.main_latency_ms_total += main_latency_ms;
+                stats.shadow_latency_ms_total += shadow_latency_ms;
+                if status / 100 == main_status / 100 {
+                    return;
+                }
+                stats.status_mismatches += 1;
+                Some(status)
+            }
+            Err(_) => {
+                stats.failures += 1;
+                None
+            }
+        };
+
+        if stats.examples.len() < MAX_EXAMPLES {
+            stats.examples.push(ShadowMismatch {
+                path,
+                main_status,
+                shadow_status,
+            });
+        }
+    });
+}
+
+/// Copies the request headers, leaving out hop-by-hop ones, the preview override and
+/// credentials, so the unreleased build never acts on behalf of a real user.
+fn forwarded_headers(headers: &HeaderMap) -> HeaderMap {
+    let mut forwarded = headers.clone();
+    for name in [
+        header::CONNECTION,
+        header::CONTENT_LENGTH,
+        header::TRANSFER_ENCODING,
+        header::UPGRADE,
+        header::TE,
+        header::TRAILER,
+        header::PROXY_AUTHORIZATION,
+        header::AUTHORIZATION,
+        header::COOKIE,
+    ] {
+        forwarded.remove(name);
+    }
+    forwarded.remove("keep-alive");
+    forwarded.remove(preview::SLOT_HEADER);
+    forwarded
+}
@@ -1,24 +1,107 @@
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+use std::fmt;
+use std::io::Write;
+use std::path::Path;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
 use tokio::{
     io::{AsyncBufReadExt, BufReader},
     process::{Child, Command},
 };
 
+/// Why a supervised command did not complete successfully.
+#[derive(Debug)]
+pub enum CommandError {
+    /// The process could not be spawned at all.
+    Spawn { cmd: String, source: std::io::Error },
+    /// Waiting for the process failed.
+    Wait { cmd: String, source: std::io::Error },
+    /// The process ran but exited unsuccessfully.
+    Exit {
+        cmd: String,
+        status: std::process::ExitStatus,
+    },
+}
+
+impl fmt::Display for CommandError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CommandError::Spawn { cmd, source } => write!(f, "could not spawn {cmd}: {source}"),
+            CommandError::Wait { cmd, source } => write!(f, "could not wait for {cmd}: {source}"),
+            CommandError::Exit { cmd, status } => match status.code() {
+                Some(code) => write!(f, "{cmd} exited with status {code}"),
+                None => write!(f, "{cmd} was terminated by a signal"),
+            },
+        }
+    }
+}
+
+impl std::error::Error for CommandError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            CommandError::Spawn { source, .. } | CommandError::Wait { source, .. } => Some(source),
+            CommandError::Exit { .. } => None,
+        }
+    }
+}
+
 pub struct CommandHandle {
+    cmd: String,
     child: Child,
     log_task: tokio::task::JoinHandle<()>,
 }
 
 impl CommandHandle {
-    pub async fn wait(mut self) -> std::io::Result<std::process::ExitStatus> {
+    /// Waits for the command to exit, treating a non-zero exit status as an error.
+    pub async fn wait(mut self) -> Result<(), CommandError> {
         let status = self.child.wait().await;
         let _ = self.log_task.await;
-        status
+
+        match status {
+            Ok(status) if status.success() => Ok(()),
+            Ok(status) => Err(CommandError::Exit {
+                cmd: self.cmd,
+                status,
+            }),
+            Err(source) => Err(CommandError::Wait {
+                cmd: self.cmd,
+                source,
+            }),
+        }
+    }
+
+    pub fn id(&self) -> Option<u32> {
+        self.child.id()
+    }
+
+    /// Checks whether the process has exited without blocking.
+    pub fn try_wait(&mut self) -> std::io::Result<Option<std::process::ExitStatus>> {
+        self.child.try_wait()
     }
 
     pub async fn kill(&mut self) -> std::io::Result<()> {
         self.child.kill().await
     }
 
+    /// Sends SIGTERM and waits up to `grace` for the process to exit before killing it.
+    pub async fn terminate(&mut self, grace: std::time::Duration) -> std::io::Result<()> {
+        let Some(pid) = self.child.id() else {
+            // already exited
+            return Ok(());
+        };
+
+        let term_sent = send_signal(pid, libc::SIGTERM).is_ok();
+
+        if term_sent && tokio::time::timeout(grace, self.child.wait()).await.is_ok() {
+            let _ = (&mut self.log_task).await;
+            return Ok(());
+        }
+
+        tracing::warn!("{} did not exit after SIGTERM, sending SIGKILL", self.cmd);
+        self.child.kill().await
+    }
+
     pub fn detach(mut self) {
         tokio::spawn(async move {
             let _ = self.child.wait().await;
@@ -27,61 +110,202 @@ impl CommandHandle {
     }
 }
 
-pub fn run_cmd_with_logs(cmd: &str, args: &[&str], env: &[(&str, &str)]) -> CommandHandle {
-    let child_result = Command::new(cmd)
+/// Sends `signal` to a single process.
+fn send_signal(pid: u32, signal: libc::c_int) -> std::io::Result<()> {
+    let pid = libc::pid_t::try_from(pid).map_err(std::io::Error::other)?;
+    // SAFETY: kill only takes plain integers and reports failure through errno
+    if unsafe { libc::kill(pid, signal) } == 0 {
+        Ok(())
+    } else {
+        Err(std::io::Error::last_os_error())
+    }
+}
+
+/// A file that command output is copied into, shared between the commands of one deployment.
+pub type LogFile = Arc<Mutex<std::fs::File>>;
+
+pub fn run_cmd_with_logs(
+    cmd: &str,
+    args: &[&str],
+    env: &[(&str, &str)],
+) -> Result<CommandHandle, CommandError> {
+    run_cmd_with_log_file(cmd, args, env, None)
+}
+
+/// Like `run_cmd_with_logs`, but also appends every output line to `log_file`.
+pub fn run_cmd_with_log_file(
+    cmd: &str,
+    args: &[&str],
+    env: &[(&str, &str)],
+    log_file: Option<LogFile>,
+) -> Result<CommandHandle, CommandError> {
+    let mut child = Command::new(cmd)
         .args(args)
         .envs(env.iter().copied())
+        // own process group, so everything the command starts can be killed together
+        .process_group(0)
+        // a deploy step that is cancelled or times out must not leave its process behind
+        .kill_on_drop(true)
         .stdout(std::process::Stdio::piped())
         .stderr(std::process::Stdio::piped())
-        .spawn();
-
-    // If the command failed, build a dummy handle that won't do anything
-    let (child, log_task) = match child_result {
-        Ok(mut c) => {
-            // Normal successful case:
-            let stdout = c.stdout.take().expect("no stdout");
-            let stderr = c.stderr.take().expect("no stderr");
-            let prefix = format!("[{}]", cmd);
-
-            let task = tokio::spawn(async move {
-                let mut out_reader = BufReader::new(stdout).lines();
-                let mut err_reader = BufReader::new(stderr).lines();
-                let mut stdout_done = false;
-                let mut stderr_done = false;
-
-                while !stdout_done || !stderr_done {
-                    tokio::select! {
-                        line = out_reader.next_line(), if !stdout_done => {
-                            match line {
-                                Ok(Some(l)) => println!("{} {}", prefix, l),
-                                _ => stdout_done = true,
-                            }
+        .spawn()
+        .map_err(|source| {
+            tracing::error!("could not spawn process {}: {}", cmd, source);
+            CommandError::Spawn {
+                cmd: cmd.to_string(),
+                source,
+            }
+        })?;
+
+    let stdout = child.stdout.take().expect("no stdout");
+    let stderr = child.stderr.take().expect("no stderr");
+    let prefix = format!("[{}]", cmd);
+    let file_prefix = format!(
+        "[{}]",
+        Path::new(cmd)
+            .file_name()
+            .map(|name| name.to_string_lossy())
+            .unwrap_or_else(|| cmd.into())
+    );
+    let write_to_file = move |line: &str| {
+        if let Some(file) = &log_file
+            && let Ok(mut file) = file.lock()
+        {
+            let _ = writeln!(file, "{} {}", file_prefix, line);
+        }
+    };
+
+    let log_task = tokio::spawn(async move {
+        let mut out_reader = BufReader::new(stdout).lines();
+        let mut err_reader = BufReader::new(stderr).lines();
+        let mut stdout_done = false;
+        let mut stderr_done = false;
+
+        while !stdout_done || !stderr_done {
+            tokio::select! {
+                line = out_reader.next_line(), if !stdout_done => {
+                    match line {
+                        Ok(Some(l)) => {
+                            println!("{} {}", prefix, l);
+                            write_to_file(&l);
                         }
-                        line = err_reader.next_line(), if !stderr_done => {
-                            match line {
-                                Ok(Some(l)) => eprintln!("{} {}", prefix, l),
-                                _ => stderr_done = true,
-                            }
+                        _ => stdout_done = true,
+                    }
+                }
+                line = err_reader.next_line(), if !stderr_done => {
+                    match line {
+                        Ok(Some(l)) => {
+                            eprintln!("{} {}", prefix, l);
+                            write_to_file(&l);
                         }
+                        _ => stderr_done = true,
                     }
                 }
-            });
-
-            (c, task)
+            }
         }
+    });
+
+    Ok(CommandHandle {
+        cmd: cmd.to_string(),
+        child,
+        log_task,
+    })
+}
 
-        Err(err) => {
-            tracing::error!("could not spawn process {}: {}", cmd, err);
+/// Sends SIGKILL to the process group led by `pid`, e.g. a script and everything it started.
+pub fn kill_process_group(pid: u32) {
+    let killed = libc::pid_t::try_from(pid)
+        // SAFETY: killpg only takes plain integers and reports failure through errno
+        .map(|pgrp| unsafe { libc::killpg(pgrp, libc::SIGKILL) } == 0)
+        .unwrap_or(false);
 
-            // Dummy child that immediately exits with status 1
-            let dummy_child = Command::new("true").spawn().expect("failed to spawn dummy");
+    if !killed {
+        tracing::warn!("could not kill process group {}", pid);
+    }
+}
 
-            // Log task that does nothing
-            let dummy_task = tokio::spawn(async {});
+/// Spreads a percentage of requests evenly over the traffic by counting them in hundreds.
+#[derive(Default)]
+pub struct Sampler(AtomicU64);
 
-            (dummy_child, dummy_task)
-        }
+impl Sampler {
+    pub const fn new() -> Self {
+        Self(AtomicU64::new(0))
+    }
+
+    /// Position of the next request in its hundred, from 0 to 99.
+    pub fn next_bucket(&self) -> u8 {
+        (self.0.fetch_add(1, Ordering::Relaxed) % 100) as u8
+    }
+
+    /// Whether the next request falls into the first `percent` of its hundred.
+    pub fn sample(&self, percent: u8) -> bool {
+        self.next_bucket() < percent
+    }
+}
+
+type HmacSha256 = Hmac<Sha256>;
+
+/// HMAC-SHA256 of `message` keyed with `key`.
+pub fn hmac_sha256(key: &[u8], message: &[u8]) -> Option<Vec<u8>> {
+    let mut mac = HmacSha256::new_from_slice(key).ok()?;
+    mac.update(message);
+    Some(mac.finalize().into_bytes().to_vec())
+}
+
+/// Whether `signature` is the HMAC-SHA256 of `message` keyed with `key`.
+pub fn verify_hmac_sha256(key: &[u8], message: &[u8], signature: &[u8]) -> bool {
+    let Ok(mut mac) = HmacSha256::new_from_slice(key) else {
+        return false;
     };
+    mac.update(message);
+    // constant time comparison
+    mac.verify_slice(signature).is_ok()
+}
+
+/// Compares secrets without returning early, so timing does not reveal how much of them matched.
+pub fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    if a.len() != b.len() {
+        return false;
+    }
+    a.iter().zip(b).fold(0u8, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+/// Picks the value of the cookie called `name` out of a `Cookie` header.
+pub fn cookie_value<'a>(cookie_header: &'a str, name: &str) -> Option<&'a str> {
+    cookie_header
+        .split(';')
+        .filter_map(|pair| pair.trim().split_once('='))
+        .find(|(cookie, _)| *cookie == name)
+        .map(|(_, value)| value)
+}
+
+/// The `Cookie` header without the cookie called `name`, empty if nothing else is left.
+pub fn without_cookie(cookie_header: &str, name: &str) -> String {
+    cookie_header
+        .split(';')
+        .map(str::trim)
+        .filter(|pair| {
+            pair.split_once('=')
+                .is_none_or(|(cookie, _)| cookie != name)
+        })
+        .filter(|pair| !pair.is_empty())
+        .collect::<Vec<_>>()
+        .join("; ")
+}
+
+/// Milliseconds since the unix epoch, used to time deploy phases.
+pub fn unix_timestamp_millis() -> u64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|d| d.as_millis() as u64)
+        .unwrap_or(0)
+}
 
-    CommandHandle { child, log_task }
+/// Seconds since the unix epoch, used for timestamps in status output.
+pub fn unix_timestamp() -> u64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
 }
@@ -0,0 +1,338 @@
+use crate::utils;
+use axum::http::HeaderMap;
+use serde::Deserialize;
+use std::fmt;
+
+// `after` of a push that deleted the ref
+const ZERO_COMMIT: &str = "0000000000000000000000000000000000000000";
+
+/// Git hosting services whose webhook formats are understood.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Provider {
+    Github,
+    /// Gitea and Forgejo, which share the same format.
+    Gitea,
+    Gitlab,
+}
+
+impl Provider {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Provider::Github => "github",
+            Provider::Gitea => "gitea",
+            Provider::Gitlab => "gitlab",
+        }
+    }
+}
+
+/// What a git provider webhook delivery asks the supervisor to do.
+#[derive(Debug)]
+pub enum WebhookEvent {
+    /// Commits were pushed to `git_ref`.
+    Push {
+        git_ref: String,
+        commit: Option<String>,
+        deleted: bool,
+    },
+    /// The provider is checking that the webhook is reachable.
+    Ping,
+    /// Any other event type, acknowledged without deploying.
+    Other(String),
+}
+
+#[derive(Debug)]
+pub enum WebhookError {
+    /// The delivery is signed but the signature does not match the secret.
+    InvalidSignature,
+    /// The delivery is not signed and no other credentials were given.
+    Unauthenticated,
+    /// The body could not be parsed as the provider's payload.
+    InvalidPayload(String),
+}
+
+impl fmt::Display for WebhookError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            WebhookError::InvalidSignature => write!(f, "webhook signature does not match"),
+            WebhookError::Unauthenticated => {
+                write!(
+                    f,
+                    "webhook is neither signed nor authenticated with an API key"
+                )
+            }
+            WebhookError::InvalidPayload(reason) => write!(f, "invalid webhook payload: {reason}"),
+        }
+    }
+}
+
+/// The push payload fields shared by GitHub, Gitea/Forgejo and GitLab.
+#[derive(Deserialize)]
+struct PushPayload {
+    #[serde(rename = "ref")]
+    git_ref: String,
+    after: Option<String>,
+    /// GitLab only, null when the ref was deleted.
+    checkout_sha: Option<String>,
+    /// GitHub only.
+    #[serde(default)]
+    deleted: bool,
+}
+
+type VerifyFn = fn(&str, &[u8], &str) -> bool;
+
+/// Detects which provider sent the request from its event header.
+pub fn detect(headers: &HeaderMap) -> Option<Provider> {
+    if headers.contains_key("x-github-event") {
+        Some(Provider::Github)
+    } else if headers.contains_key("x-gitea-event") || headers.contains_key("x-forgejo-event") {
+        Some(Provider::Gitea)
+    } else if headers.contains_key("x-gitlab-event") {
+        Some(Provider::Gitlab)
+    } else {
+        None
+    }
+}
+
+/// Authenticates and parses a delivery from the given provider.
+/// With a secret, only deliveries signed with it are accepted. Without one, `api_key_valid`
+/// lets deliveries through when the `apikey` query parameter matched.
+pub fn parse(
+    provider: Provider,
+    headers: &HeaderMap,
+    body: &[u8],
+    secret: Option<&str>,
+    api_key_valid: bool,
+) -> Result<WebhookEvent, WebhookError> {
+    let signed: Option<(&str, VerifyFn)> = match provider {
+        Provider::Github => header_str(headers, "x-hub-signature-256")
+            .map(|signature| (signature, verify_github_signature as VerifyFn)),
+        Provider::Gitea => header_str(headers, "x-forgejo-signature")
+            .or_else(|| header_str(headers, "x-gitea-signature"))
+            .map(|signature| (signature, verify_gitea_signature as VerifyFn)),
+        Provider::Gitlab => header_str(headers, "x-gitlab-token")
+            .map(|token| (token, verify_gitlab_token as VerifyFn)),
+    };
+
+    match (secret, signed) {
+        (Some(secret), Some((signature, verify))) => {
+            if !verify(secret, body, signature) {
+                return Err(WebhookError::InvalidSignature);
+            }
+        }
+        (None, _) if api_key_valid => {}
+        _ => return Err(WebhookError::Unauthenticated),
+    }
+
+    let event = match provider {
+        Provider::Github => header_str(headers, "x-github-event"),
+        Provider::Gitea => {
+            header_str(headers, "x-forgejo-event").or_else(|| header_str(headers, "x-gitea-event"))
+        }
+        Provider::Gitlab => header_str(headers, "x-gitlab-event"),
+    }
+    .unwrap_or_default();
+
+    match (provider, event) {
+        (Provider::Github, "ping") => Ok(WebhookEvent::Ping),
+        (Provider::Github | Provider::Gitea, "push") | (Provider::Gitlab, "Push Hook") => {
+            parse_push(body)
+        }
+        (_, other) => Ok(WebhookEvent::Other(other.to_string())),
+    }
+}
+
+fn parse_push(body: &[u8]) -> Result<WebhookEvent, WebhookError> {
+    let payload: PushPayload =
+        serde_json::from_slice(body).map_err(|e| WebhookError::InvalidPayload(e.to_string()))?;
+
+    let commit = payload
+        .checkout_sha
+        .or(payload.after)
+        .filter(|commit| commit != ZERO_COMMIT);
+    let deleted = payload.deleted || commit.is_none();
+
+    Ok(WebhookEvent::Push {
+        git_ref: payload.git_ref,
+        commit,
+        deleted,
+    })
+}
+
+/// Checks an `X-Hub-Signature-256` header (`sha256=<hex>`) against the body.
+pub fn verify_github_signature(secret: &str, body: &[u8], signature: &str) -> bool {
+    let Some(signature) = signature.strip_prefix("sha256=") else {
+        return false;
+    };
+    verify_hmac_sha256(secret, body, signature)
+}
+
+/// Checks an `X-Gitea-Signature` / `X-Forgejo-Signature` header (plain hex) against the body.
+pub fn verify_gitea_signature(secret: &str, body: &[u8], signature: &str) -> bool {
+    verify_hmac_sha256(secret, body, signature)
+}
+
+/// GitLab sends the secret itself in `X-Gitlab-Token` instead of signing the body.
+pub fn verify_gitlab_token(secret: &str, _body: &[u8], token: &str) -> bool {
+    utils::constant_time_eq(secret.as_bytes(), token.as_bytes())
+}
+
+fn verify_hmac_sha256(secret: &str, body: &[u8], hex_signature: &str) -> bool {
+    let Ok(expected) = hex::decode(hex_signature.trim()) else {
+        return false;
+    };
+    utils::verify_hmac_sha256(secret.as_bytes(), body, &expected)
+}
+
+/// Whether a pushed ref is the branch deploys are tracking. Nothing matches without a branch,
+/// e.g. when none is configured and HEAD is detached.
+pub fn branch_matches(git_ref: &str, branch: Option<&str>) -> bool {
+    branch.is_some_and(|branch| {
+        git_ref
+            .strip_prefix("refs/heads/")
+            .is_some_and(|pushed| pushed == branch)
+    })
+}
+
+fn header_str<'a>(headers: &'a HeaderMap, name: &str) -> Option<&'a str> {
+    headers.get(name).and_then(|value| value.to_str().ok())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SECRET: &str = "It's a Secret to Everybody";
+    const BODY: &[u8] = b"Hello, World!";
+    // example delivery from GitHub's webhook validation docs
+    const SIGNATURE: &str = "757107ea0eb2509fc211221cce984b8a37570b6d7586c22c46f4379c8b043e17";
+
+    fn push(body: &str) -> WebhookEvent {
+        parse_push(body.as_bytes()).expect("valid push payload")
+    }
+
+    #[test]
+    fn branch_matches_only_the_tracked_branch() {
+        assert!(branch_matches("refs/heads/main", Some("main")));
+        assert!(!branch_matches("refs/heads/develop", Some("main")));
+        assert!(!branch_matches("refs/tags/main", Some("main")));
+        assert!(!branch_matches("refs/heads/main/fix", Some("main")));
+    }
+
+    #[test]
+    fn branch_matches_nothing_without_a_branch() {
+        assert!(!branch_matches("refs/heads/main", None));
+    }
+
+    #[test]
+    fn parse_push_prefers_gitlab_checkout_sha() {
+        let event = push(r#"{"ref":"refs/heads/main","after":"aaa","checkout_sha":"bbb"}"#);
+        let WebhookEvent::Push {
+            git_ref,
+            commit,
+            deleted,
+        } = event
+        else {
+            panic!("expected a push");
+        };
+        assert_eq!(git_ref, "refs/heads/main");
+        assert_eq!(commit.as_deref(), Some("bbb"));
+        assert!(!deleted);
+    }
+
+    #[test]
+    fn parse_push_detects_deleted_refs() {
+        let github = push(r#"{"ref":"refs/heads/old","after":"aaa","deleted":true}"#);
+        assert!(matches!(github, WebhookEvent::Push { deleted: true, .. }));
+
+        let zero = format!(r#"{{"ref":"refs/heads/old","after":"{ZERO_COMMIT}"}}"#);
+        let gitea = push(&zero);
+        assert!(matches!(
+            gitea,
+            WebhookEvent::Push {
+                commit: None,
+                deleted: true,
+                ..
+            }
+        ));
+    }
+
+    #[test]
+    fn parse_push_rejects_invalid_payloads() {
+        assert!(matches!(
+            parse_push(b"{\"after\":\"aaa\"}"),
+            Err(WebhookError::InvalidPayload(_))
+        ));
+    }
+
+    fn github_push_headers(signature: Option<&str>) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        headers.insert("x-github-event", "push".parse().unwrap());
+        if let Some(signature) = signature {
+            headers.insert("x-hub-signature-256", signature.parse().unwrap());
+        }
+        headers
+    }
+
+    #[test]
+    fn secret_requires_a_signature_even_with_the_api_key() {
+        let body = br#"{"ref":"refs/heads/main","after":"aaa"}"#;
+        let headers = github_push_headers(None);
+        let result = parse(Provider::Github, &headers, body, Some(SECRET), true);
+        assert!(matches!(result, Err(WebhookError::Unauthenticated)));
+    }
+
+    #[test]
+    fn api_key_authenticates_without_a_secret() {
+        let body = br#"{"ref":"refs/heads/main","after":"aaa"}"#;
+        let headers = github_push_headers(None);
+        assert!(matches!(
+            parse(Provider::Github, &headers, body, None, true),
+            Ok(WebhookEvent::Push { .. })
+        ));
+        assert!(matches!(
+            parse(Provider::Github, &headers, body, None, false),
+            Err(WebhookError::Unauthenticated)
+        ));
+    }
+
+    #[test]
+    fn only_matching_signatures_pass_with_a_secret() {
+        let signature = format!("sha256={SIGNATURE}");
+        let headers = github_push_headers(Some(&signature));
+        assert!(matches!(
+            parse(Provider::Github, &headers, BODY, Some(SECRET), false),
+            Err(WebhookError::InvalidPayload(_))
+        ));
+        assert!(matches!(
+            parse(Provider::Github, &headers, b"{}", Some(SECRET), true),
+            Err(WebhookError::InvalidSignature)
+        ));
+    }
+
+    #[test]
+    fn github_signature_needs_prefix_and_matching_hmac() {
+        let signature = format!("sha256={SIGNATURE}");
+        assert!(verify_github_signature(SECRET, BODY, &signature));
+        assert!(!verify_github_signature(SECRET, BODY, SIGNATURE));
+        assert!(!verify_github_signature("other", BODY, &signature));
+        assert!(!verify_github_signature(SECRET, b"Hello", &signature));
+    }
+
+    #[test]
+    fn gitea_signature_is_plain_hex() {
+        assert!(verify_gitea_signature(SECRET, BODY, SIGNATURE));
+        assert!(!verify_gitea_signature(SECRET, BODY, "not hex"));
+        assert!(!verify_gitea_signature(SECRET, BODY, &SIGNATURE[..10]));
+    }
+
+    #[test]
+    fn gitlab_token_is_compared_to_the_secret() {
+        assert!(verify_gitlab_token(SECRET, BODY, SECRET));
+        assert!(!verify_gitlab_token(
+            SECRET,
+            BODY,
+            "It's a Secret to Everybody!"
+        ));
+        assert!(!verify_gitlab_token(SECRET, BODY, ""));
+    }
+}